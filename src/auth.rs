@@ -0,0 +1,96 @@
+use base64;
+use config::Secret;
+use std::str::FromStr;
+
+/// Longest chunk of a SASL payload the server accepts in a single AUTHENTICATE message
+const CHUNK_SIZE: usize = 400;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SaslMechanism {
+    Plain,
+    /// The server identifies us by the TLS client certificate
+    External,
+}
+
+impl FromStr for SaslMechanism {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SaslMechanism, ()> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(SaslMechanism::Plain),
+            "external" => Ok(SaslMechanism::External),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SaslMechanism {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::External => "EXTERNAL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Sasl {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: Secret,
+}
+
+impl Sasl {
+    /// Arguments of the AUTHENTICATE messages to send once the server asks for our credentials
+    pub fn responses(&self) -> Vec<String> {
+        let payload = match self.mechanism {
+            SaslMechanism::Plain => {
+                let credentials = format!("{0}\0{0}\0{1}", self.username, self.password.0);
+                base64::encode(credentials.as_bytes())
+            }
+            // No authorization identity, the account is the one of the certificate
+            SaslMechanism::External => String::new(),
+        };
+
+        let mut responses: Vec<String> = payload
+            .as_bytes()
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect();
+        // An empty message marks the end of a payload whose last chunk was full
+        if payload.len() % CHUNK_SIZE == 0 {
+            responses.push("+".to_string());
+        }
+        responses
+    }
+}
+
+#[test]
+fn test_sasl_plain_responses() {
+    let sasl = Sasl {
+        mechanism: SaslMechanism::Plain,
+        username: "lunchbot".to_string(),
+        password: Secret("secret".to_string()),
+    };
+    assert_eq!(sasl.responses(), vec!["bHVuY2hib3QAbHVuY2hib3QAc2VjcmV0"]);
+
+    let long = Sasl {
+        password: Secret("x".repeat(282)),
+        ..sasl
+    };
+    let responses = long.responses();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].len(), 400);
+    assert_eq!(responses[1], "+");
+}
+
+#[test]
+fn test_sasl_external_responses() {
+    let sasl = Sasl {
+        mechanism: "EXTERNAL".parse().unwrap(),
+        username: "lunchbot".to_string(),
+        password: Secret(String::new()),
+    };
+    assert_eq!(sasl.mechanism.name(), "EXTERNAL");
+    assert_eq!(sasl.responses(), vec!["+"]);
+}
@@ -0,0 +1,41 @@
+use std::cmp;
+use std::time::Duration;
+
+/// Delay between reconnection attempts, doubled after every failure up to a limit
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = cmp::min(self.current * 2, self.max);
+        delay
+    }
+
+    /// Called once a connection worked for a while, so that the next outage starts over
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[test]
+fn delay_doubles_up_to_the_limit() {
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(30));
+    let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+}
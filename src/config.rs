@@ -0,0 +1,655 @@
+use auth::{Sasl, SaslMechanism};
+use failure::Error;
+use menu::{parse_url, MenuSource, Pattern};
+use regex::Regex;
+use state::ExpiryPolicy;
+use suggest::RECENT_DAYS;
+use tls::ClientCert;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml;
+
+/// Configuration as written in the file, everything is optional because environment variables
+/// can fill in the blanks
+#[derive(Deserialize, Debug, Default, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    server: Option<String>,
+    port: Option<u16>,
+    use_tls: Option<bool>,
+    tls_ca_cert: Option<PathBuf>,
+    tls_client_cert: Option<PathBuf>,
+    tls_client_key: Option<PathBuf>,
+    server_password: Option<String>,
+    nick: Option<String>,
+    nickserv_password: Option<String>,
+    sasl_mechanism: Option<String>,
+    sasl_username: Option<String>,
+    sasl_password: Option<String>,
+    channels: Option<Vec<String>>,
+    backup_file: Option<PathBuf>,
+    backup_generations: Option<u32>,
+    database_file: Option<PathBuf>,
+    command_prefixes: Option<Vec<String>>,
+    admins: Option<Vec<String>>,
+    timezone: Option<String>,
+    reminder_minutes: Option<i64>,
+    recent_days: Option<i64>,
+    expiry_policy: Option<String>,
+    expiry_minutes: Option<i64>,
+    menus: Option<BTreeMap<String, MenuConfig>>,
+}
+
+/// Menu source of a place, either `url` with `html` or `json`, or a `file`
+#[derive(Deserialize, Debug, Default, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MenuConfig {
+    url: Option<String>,
+    /// Regular expression matching a dish
+    html: Option<String>,
+    /// JSON pointer to the list of dishes
+    json: Option<String>,
+    /// Field with the name of the dish when the list contains objects
+    field: Option<String>,
+    file: Option<PathBuf>,
+}
+
+impl MenuConfig {
+    fn source(self, place: &str) -> Result<MenuSource, String> {
+        let url = match self.url {
+            Some(ref url) => {
+                Some(parse_url(url).map_err(|e| format!("menu of {}: {}", place, e))?)
+            }
+            None => None,
+        };
+        match (url, self.html, self.json, self.file) {
+            (Some(url), Some(html), None, None) => match Regex::new(&html) {
+                Ok(pattern) => Ok(MenuSource::Html {
+                    url,
+                    pattern: Pattern(pattern),
+                }),
+                Err(e) => Err(format!("menu of {}: invalid pattern: {}", place, e)),
+            },
+            (Some(url), None, Some(pointer), None) => Ok(MenuSource::Json {
+                url,
+                pointer,
+                field: self.field,
+            }),
+            (None, None, None, Some(file)) => Ok(MenuSource::File(file)),
+            _ => Err(format!(
+                "menu of {} needs either `url` with `html` or `json`, or a `file`",
+                place
+            )),
+        }
+    }
+}
+
+impl FileConfig {
+    pub fn load(file_name: &Path) -> Result<FileConfig, Error> {
+        let mut f = File::open(file_name)?;
+        let mut contents = String::new();
+        f.read_to_string(&mut contents)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+/// Password that does not show up in the logged configuration
+#[derive(Clone, Eq, PartialEq)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"***\"")
+    }
+}
+
+/// Validated configuration of the bot
+#[derive(Debug, Eq, PartialEq)]
+pub struct Settings {
+    pub server: String,
+    pub port: u16,
+    pub use_tls: bool,
+    /// DER encoded certificate of the CA that signed the server certificate
+    pub tls_ca_cert: Option<PathBuf>,
+    /// Presented to the server, which can identify the bot by it with SASL EXTERNAL
+    pub tls_client_cert: Option<ClientCert>,
+    pub server_password: Option<Secret>,
+    pub nick: String,
+    pub nickserv_password: Option<Secret>,
+    pub sasl: Option<Sasl>,
+    pub channels: Vec<String>,
+    pub backup_file: Option<PathBuf>,
+    /// Number of backups kept, the state is recovered from the newest one that can be read
+    pub backup_generations: u32,
+    /// SQLite database used instead of the backup file
+    pub database_file: Option<PathBuf>,
+    /// Commands start with one of these or with the nick followed by a colon
+    pub command_prefixes: Vec<String>,
+    /// Accounts or hostmasks like `*!~jan@*.example.com` of people allowed to restore the state
+    pub admins: Vec<String>,
+    pub timezone: Option<String>,
+    pub reminder_minutes: i64,
+    /// Places visited in this many days are suggested only when there is nothing else left
+    pub recent_days: i64,
+    pub expiry_policy: ExpiryPolicy,
+    /// Places with a daily menu and where to get it
+    pub menus: Vec<(String, MenuSource)>,
+}
+
+/// All problems found in the configuration, so that they can be fixed at once
+#[derive(Debug, Eq, PartialEq)]
+pub struct InvalidConfig(Vec<String>);
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl ::failure::Fail for InvalidConfig {}
+
+/// Looks up the environment variable first and falls back to the value from the file
+struct Sources<'a, F: 'a> {
+    env: &'a F,
+    problems: Vec<String>,
+}
+
+impl<'a, F> Sources<'a, F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn string(&mut self, var: &str, file_value: Option<String>) -> Option<String> {
+        (self.env)(var).or(file_value).filter(|v| !v.is_empty())
+    }
+
+    fn required(&mut self, var: &str, key: &str, file_value: Option<String>) -> String {
+        self.string(var, file_value).unwrap_or_else(|| {
+            self.problems
+                .push(format!("`{}` is missing, set it in the file or {}", key, var));
+            String::new()
+        })
+    }
+
+    fn parsed<T>(&mut self, var: &str, file_value: Option<T>) -> Option<T>
+    where
+        T: FromStr,
+    {
+        match (self.env)(var) {
+            Some(value) => match value.parse() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    self.problems
+                        .push(format!("{}: `{}` is not a valid value", var, value));
+                    None
+                }
+            },
+            None => file_value,
+        }
+    }
+}
+
+impl Settings {
+    /// Environment variables override values from the configuration file
+    pub fn new<F>(file: FileConfig, env: &F) -> Result<Settings, InvalidConfig>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut sources = Sources {
+            env,
+            problems: vec![],
+        };
+
+        let server = sources.required("LUNCHBOT_SERVER", "server", file.server);
+        let nick = sources.required("LUNCHBOT_NICK", "nick", file.nick);
+        let use_tls = sources.parsed("LUNCHBOT_TLS", file.use_tls).unwrap_or(false);
+        let port = sources
+            .parsed("LUNCHBOT_PORT", file.port)
+            .unwrap_or(if use_tls { 6697 } else { 6667 });
+
+        let tls_ca_cert = sources
+            .string("LUNCHBOT_TLS_CA_CERT", None)
+            .map(PathBuf::from)
+            .or(file.tls_ca_cert);
+        if let Some(ref path) = tls_ca_cert {
+            if !use_tls {
+                sources
+                    .problems
+                    .push("a CA certificate is set but TLS is disabled".to_string());
+            }
+            if !path.is_file() {
+                sources
+                    .problems
+                    .push(format!("CA certificate {} does not exist", path.display()));
+            }
+        }
+        let tls_client_cert = match (
+            sources
+                .string("LUNCHBOT_TLS_CLIENT_CERT", None)
+                .map(PathBuf::from)
+                .or(file.tls_client_cert),
+            sources
+                .string("LUNCHBOT_TLS_CLIENT_KEY", None)
+                .map(PathBuf::from)
+                .or(file.tls_client_key),
+        ) {
+            (None, None) => None,
+            (Some(cert), Some(key)) => {
+                if !use_tls {
+                    sources
+                        .problems
+                        .push("a client certificate is set but TLS is disabled".to_string());
+                }
+                for path in &[&cert, &key] {
+                    if !path.is_file() {
+                        sources
+                            .problems
+                            .push(format!("{} does not exist", path.display()));
+                    }
+                }
+                Some(ClientCert { cert, key })
+            }
+            (Some(_), None) => {
+                sources.problems.push(
+                    "a client certificate needs `tls_client_key` or LUNCHBOT_TLS_CLIENT_KEY"
+                        .to_string(),
+                );
+                None
+            }
+            (None, Some(_)) => {
+                sources.problems.push(
+                    "a client key needs `tls_client_cert` or LUNCHBOT_TLS_CLIENT_CERT".to_string(),
+                );
+                None
+            }
+        };
+        let server_password = sources
+            .string("LUNCHBOT_SERVER_PASSWORD", file.server_password)
+            .map(Secret);
+        let nickserv_password = sources
+            .string("LUNCHBOT_NICKSERV_PASSWORD", file.nickserv_password)
+            .map(Secret);
+
+        let sasl_username = sources.string("LUNCHBOT_SASL_USERNAME", file.sasl_username);
+        let sasl_password = sources.string("LUNCHBOT_SASL_PASSWORD", file.sasl_password);
+        let sasl = match sources.string("LUNCHBOT_SASL_MECHANISM", file.sasl_mechanism) {
+            None => None,
+            Some(name) => match name.parse() {
+                Ok(SaslMechanism::Plain) if sasl_password.is_none() => {
+                    sources.problems.push(
+                        "SASL PLAIN needs `sasl_password` or LUNCHBOT_SASL_PASSWORD".to_string(),
+                    );
+                    None
+                }
+                Ok(SaslMechanism::External) if tls_client_cert.is_none() => {
+                    sources.problems.push(
+                        "SASL EXTERNAL needs `tls_client_cert` and `tls_client_key`".to_string(),
+                    );
+                    None
+                }
+                Ok(mechanism) => Some(Sasl {
+                    mechanism,
+                    // Accounts are usually named after the nick
+                    username: sasl_username.unwrap_or_else(|| nick.clone()),
+                    password: Secret(sasl_password.unwrap_or_default()),
+                }),
+                Err(()) => {
+                    sources.problems.push(format!(
+                        "unknown SASL mechanism `{}`, use plain or external",
+                        name
+                    ));
+                    None
+                }
+            },
+        };
+
+        // Comma separated list of channels
+        let channels: Vec<String> = match env("LUNCHBOT_CHANNEL") {
+            Some(list) => list.split(',').map(|c| c.trim().to_string()).collect(),
+            None => file.channels.unwrap_or_default(),
+        };
+        let channels: Vec<String> = channels.into_iter().filter(|c| !c.is_empty()).collect();
+        if channels.is_empty() {
+            sources.problems.push(
+                "`channels` is missing, set it in the file or LUNCHBOT_CHANNEL".to_string(),
+            );
+        }
+        for channel in channels.iter().filter(|c| !c.starts_with('#') && !c.starts_with('&')) {
+            sources
+                .problems
+                .push(format!("`{}` is not a channel name, it should start with #", channel));
+        }
+
+        let backup_file = sources
+            .string("LUNCHBOT_BACKUP_FILE", None)
+            .map(PathBuf::from)
+            .or(file.backup_file);
+        let backup_generations = sources
+            .parsed("LUNCHBOT_BACKUP_GENERATIONS", file.backup_generations)
+            .unwrap_or(3);
+        if backup_generations == 0 {
+            sources
+                .problems
+                .push("at least one backup generation has to be kept".to_string());
+        }
+        let database_file = sources
+            .string("LUNCHBOT_DATABASE_FILE", None)
+            .map(PathBuf::from)
+            .or(file.database_file);
+        if backup_file.is_some() && database_file.is_some() {
+            sources
+                .problems
+                .push("set either a backup file or a database file, not both".to_string());
+        }
+        // Comma separated list of prefixes
+        let command_prefixes: Vec<String> = match env("LUNCHBOT_COMMAND_PREFIXES") {
+            Some(list) => list.split(',').map(|p| p.trim().to_string()).collect(),
+            None => file
+                .command_prefixes
+                .unwrap_or_else(|| vec!["lb".to_string(), "!lunch".to_string()]),
+        };
+        let command_prefixes: Vec<String> =
+            command_prefixes.into_iter().filter(|p| !p.is_empty()).collect();
+        for prefix in command_prefixes.iter().filter(|p| p.contains(char::is_whitespace)) {
+            sources
+                .problems
+                .push(format!("command prefix `{}` must not contain spaces", prefix));
+        }
+
+        // Comma separated list of accounts and hostmasks
+        let admins: Vec<String> = match env("LUNCHBOT_ADMINS") {
+            Some(list) => list.split(',').map(|a| a.trim().to_string()).collect(),
+            None => file.admins.unwrap_or_default(),
+        };
+        let admins: Vec<String> = admins.into_iter().filter(|a| !a.is_empty()).collect();
+        for admin in admins.iter().filter(|a| a.contains('!') != a.contains('@')) {
+            sources
+                .problems
+                .push(format!("admin `{}` should be an account or nick!user@host", admin));
+        }
+
+        let timezone = sources.string("LUNCHBOT_TIMEZONE", file.timezone);
+        let zoneinfo = Path::new("/usr/share/zoneinfo");
+        if let Some(ref tz) = timezone {
+            if zoneinfo.is_dir() && !zoneinfo.join(tz).is_file() {
+                sources.problems.push(format!("unknown timezone `{}`", tz));
+            }
+        }
+
+        let reminder_minutes = sources
+            .parsed("LUNCHBOT_REMINDER_MINUTES", file.reminder_minutes)
+            .unwrap_or(10);
+        if reminder_minutes < 0 {
+            sources
+                .problems
+                .push("reminder minutes must not be negative".to_string());
+        }
+
+        let recent_days = sources
+            .parsed("LUNCHBOT_RECENT_DAYS", file.recent_days)
+            .unwrap_or(RECENT_DAYS);
+        if recent_days < 0 {
+            sources
+                .problems
+                .push("recent days must not be negative".to_string());
+        }
+
+        let policy = sources
+            .string("LUNCHBOT_EXPIRY_POLICY", file.expiry_policy)
+            .unwrap_or_else(|| "after-lunch".to_string());
+        let expiry_minutes = sources
+            .parsed("LUNCHBOT_EXPIRY_MINUTES", file.expiry_minutes)
+            .unwrap_or(60);
+        let expiry_policy = ExpiryPolicy::new(&policy, expiry_minutes).unwrap_or_else(|| {
+            sources.problems.push(format!(
+                "unknown expiry policy `{}`, use after-lunch or after-creation",
+                policy
+            ));
+            ExpiryPolicy::default()
+        });
+
+        let mut menus = vec![];
+        for (place, menu) in file.menus.unwrap_or_default() {
+            match menu.source(&place) {
+                Ok(source) => menus.push((place, source)),
+                Err(problem) => sources.problems.push(problem),
+            }
+        }
+
+        if !sources.problems.is_empty() {
+            return Err(InvalidConfig(sources.problems));
+        }
+
+        Ok(Settings {
+            server,
+            port,
+            use_tls,
+            tls_ca_cert,
+            tls_client_cert,
+            server_password,
+            nick,
+            nickserv_password,
+            sasl,
+            channels,
+            backup_file,
+            backup_generations,
+            database_file,
+            command_prefixes,
+            admins,
+            timezone,
+            reminder_minutes,
+            recent_days,
+            expiry_policy,
+            menus,
+        })
+    }
+}
+
+#[cfg(test)]
+fn env_from(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+    move |name| {
+        vars.iter()
+            .find(|&&(var, _)| var == name)
+            .map(|&(_, value)| value.to_string())
+    }
+}
+
+#[test]
+fn env_overrides_file() {
+    let file: FileConfig = toml::from_str(
+        r##"
+        server = "irc.example.com"
+        nick = "lunchbot"
+        channels = ["#lunch", "#lunch-2nd-floor"]
+        use_tls = true
+        expiry_minutes = 30
+        recent_days = 5
+        "##,
+    ).unwrap();
+    let env = env_from(&[
+        ("LUNCHBOT_NICK", "obedbot"),
+        ("LUNCHBOT_CHANNEL", "#obed"),
+        ("LUNCHBOT_ADMINS", "jan, *!~ondra@*.example.com"),
+    ]);
+    let settings = Settings::new(file, &env).unwrap();
+
+    assert_eq!(settings.server, "irc.example.com");
+    assert_eq!(settings.nick, "obedbot");
+    assert_eq!(settings.channels, vec!["#obed"]);
+    assert_eq!(settings.port, 6697);
+    assert_eq!(settings.command_prefixes, vec!["lb", "!lunch"]);
+    assert_eq!(settings.admins, vec!["jan", "*!~ondra@*.example.com"]);
+    assert_eq!(settings.recent_days, 5);
+    assert_eq!(
+        settings.expiry_policy,
+        ExpiryPolicy::new("after-lunch", 30).unwrap()
+    );
+}
+
+#[test]
+fn command_prefixes() {
+    let file = |extra: &str| -> FileConfig {
+        let text = format!("server = \"irc\"\nnick = \"lb\"\nchannels = [\"#a\"]\n{}", extra);
+        toml::from_str(&text).unwrap()
+    };
+    let prefixes = |file, env| Settings::new(file, env).unwrap().command_prefixes;
+
+    let no_env = env_from(&[]);
+    assert_eq!(prefixes(file(""), &no_env), vec!["lb", "!lunch"]);
+    assert_eq!(
+        prefixes(file("command_prefixes = [\"!lunch\", \"obed\"]"), &no_env),
+        vec!["!lunch", "obed"]
+    );
+    let env = env_from(&[("LUNCHBOT_COMMAND_PREFIXES", "!lunch, !obed")]);
+    assert_eq!(prefixes(file(""), &env), vec!["!lunch", "!obed"]);
+}
+
+#[test]
+fn all_problems_are_reported() {
+    let env = env_from(&[
+        ("LUNCHBOT_PORT", "sixty"),
+        ("LUNCHBOT_CHANNEL", "lunch"),
+        ("LUNCHBOT_EXPIRY_POLICY", "never"),
+    ]);
+    let problems = Settings::new(FileConfig::default(), &env).unwrap_err().0;
+
+    assert_eq!(
+        problems,
+        vec![
+            "`server` is missing, set it in the file or LUNCHBOT_SERVER",
+            "`nick` is missing, set it in the file or LUNCHBOT_NICK",
+            "LUNCHBOT_PORT: `sixty` is not a valid value",
+            "`lunch` is not a channel name, it should start with #",
+            "unknown expiry policy `never`, use after-lunch or after-creation",
+        ]
+    );
+}
+
+#[test]
+fn authentication_settings() {
+    let file: FileConfig = toml::from_str(
+        r##"
+        server = "irc.example.com"
+        nick = "lunchbot"
+        channels = ["#lunch"]
+        use_tls = true
+        nickserv_password = "hunter2"
+        sasl_mechanism = "plain"
+        "##,
+    ).unwrap();
+    let env = env_from(&[("LUNCHBOT_SASL_PASSWORD", "secret")]);
+    let settings = Settings::new(file, &env).unwrap();
+
+    assert_eq!(settings.nickserv_password, Some(Secret("hunter2".to_string())));
+    assert_eq!(
+        settings.sasl,
+        Some(Sasl {
+            mechanism: SaslMechanism::Plain,
+            username: "lunchbot".to_string(),
+            password: Secret("secret".to_string()),
+        })
+    );
+    assert!(!format!("{:?}", settings).contains("secret"));
+
+    let env = env_from(&[
+        ("LUNCHBOT_SERVER", "irc.example.com"),
+        ("LUNCHBOT_NICK", "lunchbot"),
+        ("LUNCHBOT_CHANNEL", "#lunch"),
+        ("LUNCHBOT_SASL_MECHANISM", "external"),
+    ]);
+    let problems = Settings::new(FileConfig::default(), &env).unwrap_err().0;
+    assert_eq!(problems, vec!["SASL EXTERNAL needs `tls_client_cert` and `tls_client_key`"]);
+
+    let file: FileConfig = toml::from_str(
+        r##"
+        server = "irc.example.com"
+        nick = "lunchbot"
+        channels = ["#lunch"]
+        use_tls = true
+        tls_client_cert = "Cargo.toml"
+        tls_client_key = "src/main.rs"
+        sasl_mechanism = "external"
+        "##,
+    ).unwrap();
+    let settings = Settings::new(file, &env_from(&[])).unwrap();
+    assert_eq!(
+        settings.tls_client_cert,
+        Some(ClientCert {
+            cert: PathBuf::from("Cargo.toml"),
+            key: PathBuf::from("src/main.rs"),
+        })
+    );
+    assert_eq!(settings.sasl.unwrap().mechanism, SaslMechanism::External);
+
+    let env = env_from(&[
+        ("LUNCHBOT_SERVER", "irc.example.com"),
+        ("LUNCHBOT_NICK", "lunchbot"),
+        ("LUNCHBOT_CHANNEL", "#lunch"),
+        ("LUNCHBOT_TLS_CLIENT_CERT", "Cargo.toml"),
+    ]);
+    let problems = Settings::new(FileConfig::default(), &env).unwrap_err().0;
+    assert_eq!(
+        problems,
+        vec!["a client certificate needs `tls_client_key` or LUNCHBOT_TLS_CLIENT_KEY"]
+    );
+}
+
+#[test]
+fn menu_sources() {
+    let file: FileConfig = toml::from_str(
+        r##"
+        server = "irc.example.com"
+        nick = "lunchbot"
+        channels = ["#lunch"]
+
+        [menus.Winston]
+        url = "https://winston.example.com/menu"
+        html = '<td class="dish">(.*?)</td>'
+
+        [menus.Pho]
+        url = "http://pho.example.com/api/menu"
+        json = "/today"
+        field = "name"
+
+        [menus.Kocka]
+        url = "http://kocka.example.com"
+        file = "/srv/menus/kocka.txt"
+        "##,
+    ).unwrap();
+    let problems = Settings::new(file, &env_from(&[])).unwrap_err().0;
+    assert_eq!(
+        problems,
+        vec!["menu of Kocka needs either `url` with `html` or `json`, or a `file`"]
+    );
+
+    let file: FileConfig = toml::from_str(
+        r##"
+        server = "irc.example.com"
+        nick = "lunchbot"
+        channels = ["#lunch"]
+
+        [menus.Pho]
+        url = "http://pho.example.com/api/menu"
+        json = "/today"
+        "##,
+    ).unwrap();
+    let settings = Settings::new(file, &env_from(&[])).unwrap();
+    assert_eq!(
+        settings.menus,
+        vec![(
+            "Pho".to_string(),
+            MenuSource::Json {
+                url: parse_url("http://pho.example.com/api/menu").unwrap(),
+                pointer: "/today".to_string(),
+                field: None,
+            },
+        )]
+    );
+}
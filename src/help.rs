@@ -0,0 +1,288 @@
+use syntax::{did_you_mean, COMMANDS};
+
+struct CommandHelp {
+    name: &'static str,
+    summary: &'static str,
+    details: &'static [&'static str],
+    examples: &'static [&'static str],
+}
+
+const HELP: &'static [CommandHelp] = &[
+    CommandHelp {
+        name: "propose",
+        summary: "propose a place and time for lunch",
+        details: &[
+            "The group members get highlighted and reminded before lunch.",
+            "With `meet` you can say where and when to gather before going to the place.",
+            "Time can be 12:00, 12.30, 12, noon or in 20 min; quote names containing spaces.",
+        ],
+        examples: &[
+            "propose winston 12:00",
+            "propose \"U Kulečníku\" at noon to coreserv1",
+            "propose winston 11:45 to coreserv1 meet lobby 11:40",
+        ],
+    },
+    CommandHelp {
+        name: "list",
+        summary: "list proposals (the default), groups or known places",
+        details: &["Proposals are sorted by time and show their id and who is going."],
+        examples: &["list", "list groups", "list places"],
+    },
+    CommandHelp {
+        name: "group",
+        summary: "create, rename, show or remove a group of users",
+        details: &[
+            "Use base nicks without suffixes like |wfh, the bot finds the current ones.",
+            "Only whoever created a group and admins can rename or remove it.",
+        ],
+        examples: &[
+            "group add coreserv1 jan,ondra,tester",
+            "group show coreserv1",
+            "group rename coreserv1 core",
+            "group remove coreserv1",
+        ],
+    },
+    CommandHelp {
+        name: "add",
+        summary: "add a user to an existing group",
+        details: &[],
+        examples: &["add honza to coreserv1"],
+    },
+    CommandHelp {
+        name: "remove",
+        summary: "remove a user from a group",
+        details: &["Only whoever created the group and admins can remove others."],
+        examples: &["remove honza from coreserv1"],
+    },
+    CommandHelp {
+        name: "join-group",
+        summary: "add yourself to a group",
+        details: &["Suffixes of your nick like |wfh are left out."],
+        examples: &["join-group coreserv1"],
+    },
+    CommandHelp {
+        name: "leave-group",
+        summary: "remove yourself from a group",
+        details: &[],
+        examples: &["leave-group coreserv1"],
+    },
+    CommandHelp {
+        name: "join",
+        summary: "say you are going to a proposed lunch",
+        details: &["Use the id shown by `list`."],
+        examples: &["join 3"],
+    },
+    CommandHelp {
+        name: "leave",
+        summary: "say you are not going to a proposed lunch after all",
+        details: &[],
+        examples: &["leave 3"],
+    },
+    CommandHelp {
+        name: "cancel",
+        summary: "remove a proposal",
+        details: &["Only whoever proposed it and admins can cancel it."],
+        examples: &["cancel 3"],
+    },
+    CommandHelp {
+        name: "edit",
+        summary: "change the place or time of a proposal",
+        details: &[
+            "Only whoever proposed it and admins can edit it.",
+            "Changing the time resets the reminder.",
+        ],
+        examples: &["edit 3 time 12:15", "edit 3 place 'taste of india'"],
+    },
+    CommandHelp {
+        name: "place",
+        summary: "manage the restaurants known in the channel",
+        details: &[
+            "Proposals of a known place use its name, whether it is written by an alias,",
+            "in a different case or with a small typo.",
+            "Only whoever added a place and admins can remove it.",
+        ],
+        examples: &[
+            "place add Winston walk 5 address 'Vinohradská 12' notes 'cash only'",
+            "place alias Winston win",
+            "place tag Winston vegetarian,vegan",
+            "place info win",
+            "place remove Winston",
+        ],
+    },
+    CommandHelp {
+        name: "suggest",
+        summary: "pick a place for lunch",
+        details: &[
+            "Places the group likes are more likely, places visited in the last few days are",
+            "avoided and so are places vetoed by any member or missing a tag of their diets.",
+            "With a time the suggestion is proposed right away.",
+        ],
+        examples: &["suggest", "suggest for coreserv1 at 12:00"],
+    },
+    CommandHelp {
+        name: "poll",
+        summary: "let the channel vote between places, or show the open poll",
+        details: &[
+            "The poll closes at the deadline (in 30 minutes by default) and the place with the",
+            "most votes is proposed for the lunch time, which defaults to the deadline.",
+        ],
+        examples: &[
+            "poll winston pho \"U Kulečníku\" by 11:30",
+            "poll winston pho by 11:30 at 11:45 to coreserv1",
+            "poll",
+        ],
+    },
+    CommandHelp {
+        name: "vote",
+        summary: "vote for a place in the open poll",
+        details: &["Use the number shown by `poll`, voting again changes the vote."],
+        examples: &["vote 2"],
+    },
+    CommandHelp {
+        name: "veto",
+        summary: "never get a place suggested",
+        details: &["Only in a private message to the bot."],
+        examples: &["veto winston", "veto remove winston"],
+    },
+    CommandHelp {
+        name: "diet",
+        summary: "get suggested only places with the given tags",
+        details: &[
+            "Tags of places are set by `place tag`.",
+            "Only in a private message to the bot.",
+        ],
+        examples: &["diet vegetarian", "diet none"],
+    },
+    CommandHelp {
+        name: "subscribe",
+        summary: "get reminders in a private message too",
+        details: &["Only in a private message to the bot."],
+        examples: &["subscribe"],
+    },
+    CommandHelp {
+        name: "unsubscribe",
+        summary: "get reminders only in the channel",
+        details: &["Only in a private message to the bot."],
+        examples: &["unsubscribe"],
+    },
+    CommandHelp {
+        name: "menu",
+        summary: "show today's menu of a place",
+        details: &[
+            "Menus of places listed in the configuration are downloaded once a day.",
+            "Quote names with spaces.",
+        ],
+        examples: &["menu winston", "menu \"U Kulečníku\""],
+    },
+    CommandHelp {
+        name: "stats",
+        summary: "show statistics about past lunches",
+        details: &[
+            "places: most popular places this month, people: who eats with whom,",
+            "time: average lunch time, streaks: working days in a row each group went out.",
+            "Only people who joined a proposal count as having been there.",
+        ],
+        examples: &["stats", "stats people jan"],
+    },
+    CommandHelp {
+        name: "dumpstate",
+        summary: "print the state of the bot as JSON",
+        details: &["Only for admins."],
+        examples: &[],
+    },
+    CommandHelp {
+        name: "restore",
+        summary: "replace the state of the bot with JSON printed by dumpstate",
+        details: &["Only for admins, the current state is lost except for past lunches."],
+        examples: &[],
+    },
+    CommandHelp {
+        name: "help",
+        summary: "show this overview or details about a command",
+        details: &[],
+        examples: &["help propose"],
+    },
+];
+
+/// Usage of the command without the `lb` it is written with in `COMMANDS`
+fn usage(name: &str) -> &'static str {
+    COMMANDS
+        .iter()
+        .find(|&&(command, _)| command == name)
+        .map_or("", |&(_, usage)| usage.trim_start_matches("lb "))
+}
+
+/// Commands in the text start with `prefix`, the first one from the configuration
+pub fn help(topic: Option<&str>, prefix: &str) -> String {
+    let mut lines = vec![];
+    match topic {
+        None => {
+            lines.push(format!("Commands (use `{} help <command>` for details):", prefix));
+            for command in HELP {
+                lines.push(format!("  {} - {}", command.name, command.summary));
+            }
+            lines.push(format!(
+                "Instead of `{}` the bot can be addressed by its nick, like `lunchbot: list`. \
+                 In a private message to the bot it can be left out, start the command with \
+                 the channel it is for when the bot is in more of them, like `#lunch list`.",
+                prefix
+            ));
+        }
+        Some(topic) => match HELP.iter().find(|c| c.name == topic) {
+            Some(command) => {
+                lines.push(format!("{} {} - {}", prefix, usage(command.name), command.summary));
+                lines.extend(command.details.iter().map(|d| d.to_string()));
+                for example in command.examples {
+                    lines.push(format!("  e.g. {} {}", prefix, example));
+                }
+            }
+            None => match did_you_mean(topic, HELP.iter().map(|c| c.name)) {
+                Some(similar) => lines.push(format!(
+                    "unknown command `{}`, did you mean `{}`?",
+                    topic, similar
+                )),
+                None => lines.push(format!(
+                    "unknown command `{}`, try `{} help` for the list of commands",
+                    topic, prefix
+                )),
+            },
+        },
+    }
+    lines.join("\r\n")
+}
+
+#[test]
+fn every_command_has_help() {
+    for &(name, _) in COMMANDS {
+        assert!(HELP.iter().any(|c| c.name == name), "missing help for {}", name);
+    }
+}
+
+#[test]
+fn command_help_shows_usage_and_examples() {
+    let text = help(Some("join"), "lb");
+    assert_eq!(
+        text,
+        "lb join <proposal-id> - say you are going to a proposed lunch\r\n\
+         Use the id shown by `list`.\r\n  \
+         e.g. lb join 3"
+    );
+    assert!(help(Some("propse"), "lb").contains("did you mean `propose`?"));
+
+    // The configured prefix is shown instead of lb
+    assert_eq!(
+        help(Some("edit"), "!lunch"),
+        "!lunch edit <proposal-id> (place <place>|time <time>) - \
+         change the place or time of a proposal\r\n\
+         Only whoever proposed it and admins can edit it.\r\n\
+         Changing the time resets the reminder.\r\n  \
+         e.g. !lunch edit 3 time 12:15\r\n  \
+         e.g. !lunch edit 3 place 'taste of india'"
+    );
+    assert!(help(None, "!lunch").starts_with("Commands (use `!lunch help <command>` for details)"));
+    assert!(help(None, "!lunch").contains("Instead of `!lunch` the bot"));
+    assert_eq!(
+        help(Some("xyzzy"), "!lunch"),
+        "unknown command `xyzzy`, try `!lunch help` for the list of commands"
+    );
+}
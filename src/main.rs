@@ -1,36 +1,113 @@
+extern crate base64;
+extern crate chrono;
 extern crate env_logger;
+#[macro_use]
 extern crate failure;
+extern crate futures;
+extern crate hyper;
+extern crate hyper_tls;
 extern crate irc;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate lazy_static;
+extern crate native_tls;
+extern crate openssl;
+extern crate rand;
 extern crate regex;
+extern crate rusqlite;
+extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_timer;
+extern crate tokio_tls;
+extern crate toml;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 
+use chrono::Local;
 use failure::Error;
 use irc::client::prelude::*;
 use irc::error::IrcError;
 use irc::proto::caps::Capability;
+use irc::proto::CapSubCommand;
+use std::io;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Handle;
 
 mod syntax;
-use syntax::LunchCommand;
+use syntax::{command_body, LunchCommand};
+
+mod auth;
+use auth::Sasl;
+
+mod help;
+
+mod stats;
+
+mod suggest;
+
+mod places;
+
+mod menu;
+use menu::{format_menu, Menus};
+
+mod permissions;
+use permissions::Identity;
 
 mod state;
-use state::{update_state, LunchBotState, StateUpdateCallbacks, User};
+use state::{
+    private_channel, update_state, ChannelStates, ExpiryPolicy, StateUpdateCallbacks, User,
+};
 
 mod storage;
+use storage::{JsonFile, NoStorage, Storage};
+
+mod sqlite;
+use sqlite::Sqlite;
+
+mod migration;
+
+mod config;
+use config::{FileConfig, Settings};
+
+mod time;
+
+mod backoff;
+use backoff::Backoff;
 
-impl<'a> StateUpdateCallbacks for &'a IrcClient {
+mod tls;
+use tls::Relay;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10 * 60);
+/// A connection that lasted this long was not part of the same outage
+const STABLE_CONNECTION: Duration = Duration::from_secs(5 * 60);
+/// Servers that never answer the SASL request must not keep the registration open forever
+const CAP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Replies longer than this go to the sender privately
+const LONG_REPLY_LINES: usize = 4;
+const LONG_REPLY_CHARS: usize = 400;
+
+/// What commands need from the connection
+struct Callbacks<'a> {
+    client: &'a IrcClient,
+    handle: &'a Handle,
+    menus: &'a Arc<Mutex<Menus>>,
+    /// Where replies that are not ready right away are sent
+    target: &'a str,
+    /// Who asked in a channel, long replies go to them privately
+    requester: Option<&'a str>,
+}
+
+impl<'a> StateUpdateCallbacks for Callbacks<'a> {
     fn get_list_of_users(&self, channel: &str) -> Vec<User> {
-        if let Some(list) = self.list_users(channel) {
+        if let Some(list) = self.client.list_users(channel) {
             list.into_iter()
                 .map(|u| u.get_nickname().to_string())
                 .collect()
@@ -39,46 +116,225 @@ impl<'a> StateUpdateCallbacks for &'a IrcClient {
             vec![]
         }
     }
-}
 
-fn run() -> Result<(), Error> {
-    let nick: String = std::env::var("LUNCHBOT_NICK")?;
-    let server: String = std::env::var("LUNCHBOT_SERVER")?;
-    let channel: String = std::env::var("LUNCHBOT_CHANNEL")?;
-    let port: u16 = std::env::var("LUNCHBOT_PORT")?.parse()?;
-    let backup_file = std::env::var("LUNCHBOT_BACKUP_FILE");
+    fn menu(&self, place: &str) -> Option<String> {
+        let today = Local::today().naive_local();
+        let menus = self.menus.lock().unwrap();
+        let source = match menus.source(place) {
+            Some(source) => source,
+            None => return Some(menus.unknown(place)),
+        };
+        if let Some(dishes) = menus.cached(place, today) {
+            return Some(format_menu(place, dishes));
+        }
 
-    let mut state = LunchBotState::new(&channel);
+        let client = self.client.clone();
+        let cache = self.menus.clone();
+        let target = self.target.to_string();
+        let requester = self.requester.map(str::to_string);
+        let place = place.to_string();
+        let download = menu::fetch(source, self.handle).then(move |result| {
+            let reply = match result {
+                Ok(dishes) => {
+                    let reply = format_menu(&place, &dishes);
+                    cache.lock().unwrap().store(&place, today, dishes);
+                    reply
+                }
+                Err(e) => {
+                    error!("Failed to get the menu of {}: {}", place, e);
+                    format!("Failed to get the menu of {}: {}", place, e)
+                }
+            };
+            send_reply(&client, &target, requester.as_ref().map(String::as_str), &reply);
+            Ok::<(), ()>(())
+        });
+        self.handle.spawn(download);
+        None
+    }
 
-    if let Ok(file_name) = &backup_file {
-        if let Err(e) = storage::recover_state(&mut state, Path::new(&file_name)) {
-            error!("Failed to recover state: {}", e);
+    fn notify(&self, nick: &str, message: &str) {
+        if let Err(e) = self.client.send_privmsg(nick, message) {
+            error!("send_privmsg: {:?}", e);
         }
     }
+}
 
-    let config = Config {
-        nickname: Some(nick),
-        server: Some(server),
-        channels: Some(vec![channel]),
-        port: Some(port),
-        ..Default::default()
+fn load_settings() -> Result<Settings, Error> {
+    // The configuration file is optional, everything can be set by environment variables
+    let path = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var("LUNCHBOT_CONFIG").ok());
+    let file = match path {
+        Some(path) => FileConfig::load(Path::new(&path))
+            .map_err(|e| format_err!("Failed to load {}: {}", path, e))?,
+        None => FileConfig::default(),
     };
+    Ok(Settings::new(file, &|var| std::env::var(var).ok())?)
+}
 
-    let mut reactor = IrcReactor::new()?;
-    let client = match reactor.prepare_client_and_connect(&config) {
-        Ok(c) => c,
-        Err(_e) => {
+/// Without SASL this is the usual `identify`, with SASL the capability negotiation stays open
+/// until `authenticate` is done with it
+fn register(client: &IrcClient, sasl: &Option<Sasl>) -> Result<(), IrcError> {
+    client.send_cap_req(&[Capability::MultiPrefix])?;
+    // Requested on its own, servers reject the whole request if they do not know one of them
+    client.send_cap_req(&[Capability::AccountTag])?;
+    if sasl.is_none() {
+        return client.identify();
+    }
+
+    client.send_cap_req(&[Capability::Sasl])?;
+    if client.config().password() != "" {
+        client.send(Command::PASS(client.config().password().to_owned()))?;
+    }
+    client.send(Command::NICK(client.config().nickname()?.to_owned()))?;
+    client.send(Command::USER(
+        client.config().username().to_owned(),
+        "0".to_owned(),
+        client.config().real_name().to_owned(),
+    ))?;
+    Ok(())
+}
+
+/// Account the sender is logged in to, servers with the account-tag capability tag messages with
+/// it
+fn account(message: &Message) -> Option<String> {
+    message
+        .tags
+        .as_ref()?
+        .iter()
+        .find(|tag| tag.0 == "account")
+        .and_then(|tag| tag.1.clone())
+}
+
+/// Walks through the SASL exchange, the registration is finished whether it succeeds or not.
+/// `negotiating` is cleared once it is.
+fn authenticate(
+    client: &IrcClient,
+    sasl: &Sasl,
+    message: &Message,
+    negotiating: &AtomicBool,
+) -> Result<(), IrcError> {
+    let end_negotiation = || {
+        negotiating.store(false, Ordering::SeqCst);
+        client.send(Command::CAP(None, CapSubCommand::END, None, None))
+    };
+    let mentions_sasl = |field: &Option<String>, list: &Option<String>| {
+        field
+            .iter()
+            .chain(list.iter())
+            .any(|caps| caps.split_whitespace().any(|c| c == "sasl"))
+    };
+
+    match message.command {
+        Command::CAP(_, CapSubCommand::ACK, ref field, ref list) if mentions_sasl(field, list) => {
+            client.send(Command::AUTHENTICATE(sasl.mechanism.name().to_string()))?;
+        }
+        Command::CAP(_, CapSubCommand::NAK, ref field, ref list) if mentions_sasl(field, list) => {
+            error!("The server does not support SASL, continuing without it");
+            end_negotiation()?;
+        }
+        Command::AUTHENTICATE(ref data) if data == "+" => {
+            for response in sasl.responses() {
+                client.send_sasl(&response)?;
+            }
+        }
+        Command::Response(Response::RPL_SASLSUCCESS, _, _) => {
+            info!("Authenticated as {}", sasl.username);
+            end_negotiation()?;
+        }
+        Command::Response(Response::ERR_SASLFAIL, _, ref reason)
+        | Command::Response(Response::ERR_SASLTOOLONG, _, ref reason)
+        | Command::Response(Response::ERR_SASLABORT, _, ref reason) => {
             error!(
-                "Could not connect to the server: {}",
-                &config.server.unwrap()
+                "SASL authentication failed: {}",
+                reason.as_ref().map_or("no reason given", |r| r.as_str())
             );
-            panic!("Don't know how to handle this error yet")
+            end_negotiation()?;
+        }
+        // Servers without capability negotiation register the client right away
+        Command::Response(Response::RPL_WELCOME, _, _) => {
+            negotiating.store(false, Ordering::SeqCst)
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn is_long(reply: &str) -> bool {
+    reply.split("\r\n").count() > LONG_REPLY_LINES || reply.len() > LONG_REPLY_CHARS
+}
+
+/// Sends the reply to `target`, or privately to the `requester` when it is long. Lines of a
+/// reply are separated by CRLF, `send_privmsg` sends each of them as a separate message.
+fn send_reply(client: &IrcClient, target: &str, requester: Option<&str>, reply: &str) {
+    let reply = match requester {
+        Some(nick) if is_long(reply) => {
+            // Only the sender asked, the rest of the channel does not need to read it
+            if let Err(e) = client.send_privmsg(nick, reply) {
+                error!("send_privmsg: {:?}", e);
+            }
+            format!("{}: the reply is long, I sent it to you privately", nick)
         }
+        _ => reply.to_string(),
     };
-    client.send_cap_req(&[Capability::MultiPrefix])?;
-    client.identify()?;
+    if let Err(e) = client.send_privmsg(target, &reply) {
+        error!("send_privmsg: {:?}", e);
+    }
+}
+
+type SharedStorage = Arc<Mutex<Box<dyn Storage + Send>>>;
 
-    let state = Arc::new(Mutex::new(state));
+/// Everything a connection needs besides the irc configuration, kept across reconnects
+#[derive(Clone)]
+struct Session {
+    /// Private messages can pick one of them, the first one by default
+    channels: Vec<String>,
+    reminder_minutes: i64,
+    expiry_policy: ExpiryPolicy,
+    prefixes: Vec<String>,
+    sasl: Option<Sasl>,
+    /// Menus downloaded today stay cached after a reconnect
+    menus: Arc<Mutex<Menus>>,
+    /// Set when the bot presents a client certificate
+    relay: Option<Arc<Relay>>,
+}
+
+/// Runs until the connection fails or the server closes it
+fn run_session(
+    config: &Config,
+    session: Session,
+    state: Arc<Mutex<ChannelStates>>,
+    storage: SharedStorage,
+) -> Result<(), Error> {
+    let Session {
+        channels,
+        reminder_minutes,
+        expiry_policy,
+        prefixes,
+        sasl,
+        menus,
+        relay,
+    } = session;
+
+    let mut reactor = IrcReactor::new()?;
+    // The irc crate cannot present a client certificate, it talks to the relay instead
+    let relayed;
+    let config = match relay {
+        Some(ref relay) => {
+            let local = relay.listen(&reactor.inner_handle())?;
+            relayed = Config {
+                server: Some(local.ip().to_string()),
+                port: Some(local.port()),
+                use_ssl: Some(false),
+                ..config.clone()
+            };
+            &relayed
+        }
+        None => config,
+    };
+    let client = reactor.prepare_client_and_connect(config)?;
+    register(&client, &sasl)?;
+    let handle = reactor.inner_handle();
 
     let timer = tokio_timer::wheel()
         .tick_duration(Duration::from_secs(1))
@@ -88,56 +344,244 @@ fn run() -> Result<(), Error> {
 
     let send_interval = timer.interval(Duration::from_secs(60));
 
+    let negotiating = Arc::new(AtomicBool::new(sasl.is_some()));
+    let cap_client = client.clone();
+    let cap_negotiating = negotiating.clone();
+    reactor.register_future(timer.sleep(CAP_TIMEOUT).map_err(IrcError::Timer).and_then(
+        move |()| {
+            if cap_negotiating.load(Ordering::SeqCst) {
+                error!("The server did not finish the SASL exchange, continuing without it");
+                cap_client.send(Command::CAP(None, CapSubCommand::END, None, None))?;
+            }
+            Ok(())
+        },
+    ));
+
     let sc = state.clone();
+    let timer_storage = storage.clone();
+    let reminder_client = client.clone();
+    let reminder_handle = handle.clone();
+    let reminder_menus = menus.clone();
 
     reactor.register_future(send_interval.map_err(IrcError::Timer).for_each(move |_| {
-        // Remove old proposals
-        let state = &mut sc.lock().unwrap();
-        let num_before = state.num_of_proposals();
-        state.remove_old_proposals();
-        let num_after = state.num_of_proposals();
-        let removed = num_before - num_after;
-        if removed > 0 {
-            info!("Removing {} old proposals", removed);
-        }
-
-        // Backup state
-        if let Ok(ref v) = backup_file {
-            if let Err(e) = storage::backup_state(&state, Path::new(&v)) {
-                error!("Failed to backup the state: {}", e);
+        // Same order as in the message handler
+        let storage = &mut timer_storage.lock().unwrap();
+        let states = &mut sc.lock().unwrap();
+        let mut changed = vec![];
+        let (channels, preferences) = states.states_and_preferences_mut();
+        for state in channels {
+            // Remove old proposals
+            let expired = state.remove_old_proposals(expiry_policy, Local::now());
+            if !expired.is_empty() {
+                info!("Removing {} old proposals from {}", expired.len(), state.channel());
+                if let Err(e) = storage.archive(state.channel(), &expired) {
+                    error!("Failed to archive proposals: {}", e);
+                }
+                changed.push(state.channel().to_string());
+            }
+
+            // Remind people of upcoming lunches
+            let channel = state.channel().to_string();
+            let callbacks = Callbacks {
+                client: &reminder_client,
+                handle: &reminder_handle,
+                menus: &reminder_menus,
+                target: &channel,
+                requester: None,
+            };
+            let ahead = chrono::Duration::minutes(reminder_minutes);
+            let reminders = state.take_reminders(Local::now(), ahead, preferences, &callbacks);
+            if !reminders.is_empty() {
+                changed.push(state.channel().to_string());
+            }
+            for reminder in reminders {
+                if let Err(e) = reminder_client.send_privmsg(state.channel(), &reminder) {
+                    error!("send_privmsg: {:?}", e);
+                }
+            }
+
+            // Announce the results of polls that are over
+            if let Some(result) = state.close_poll(Local::now(), &callbacks) {
+                changed.push(state.channel().to_string());
+                if let Err(e) = reminder_client.send_privmsg(state.channel(), &result) {
+                    error!("send_privmsg: {:?}", e);
+                }
+            }
+        }
+
+        changed.dedup();
+        for channel in changed {
+            if let Err(e) = storage.save(states, &channel) {
+                error!("Failed to save the state of {}: {}", channel, e);
             }
         }
 
         Ok(())
     }));
 
-    reactor.register_client_with_handler(client, move |irc_client, message| {
+    let irc_client = client.clone();
+    let messages = client.stream().for_each(move |message| {
+        if let Some(ref sasl) = sasl {
+            authenticate(&irc_client, sasl, &message, &negotiating)?;
+        }
         match message.command {
             Command::PRIVMSG(ref target, ref line) => {
-                if line.starts_with("lb ") {
-                    // Update state and store the response
-                    let response = update_state(line, state.clone(), &irc_client);
-                    if let Some(t) = message.response_target() {
-                        if let Err(e) = irc_client.send_privmsg(t, &response) {
-                            error!("send_privmsg: {:?}", e);
-                        }
-                    } else {
+                let private = !target.starts_with('#') && !target.starts_with('&');
+                let nick = irc_client.current_nickname();
+                // Nobody types the prefix in a query, CTCP requests are not commands though
+                let line = match command_body(line, &prefixes, nick) {
+                    Some(body) => body,
+                    None if private && !line.starts_with('\u{1}') => line.as_str(),
+                    None => return Ok(()),
+                };
+                let sender = Identity {
+                    nick: message.source_nickname().unwrap_or("").to_string(),
+                    account: account(&message),
+                    hostmask: message.prefix.clone(),
+                };
+                let reply_to = match message.response_target() {
+                    Some(t) => t,
+                    None => {
                         error!("response_target is None; fallback to PRIVMSG::target");
-                        if let Err(e) = irc_client.send_privmsg(target, &response) {
-                            error!("send_privmsg: {:?}", e);
+                        target.as_str()
+                    }
+                };
+                let (channel, line) = if !private {
+                    (target.as_str(), line)
+                } else {
+                    match private_channel(line, &channels) {
+                        Ok(channel_and_line) => channel_and_line,
+                        Err(refusal) => {
+                            send_reply(&irc_client, reply_to, None, &refusal);
+                            return Ok(());
                         }
                     }
+                };
+                let callbacks = Callbacks {
+                    client: &irc_client,
+                    handle: &handle,
+                    menus: &menus,
+                    target: reply_to,
+                    requester: if private { None } else { Some(sender.nick.as_str()) },
+                };
+                // Update state and store the response
+                let mut storage = storage.lock().unwrap();
+                let response = update_state(
+                    line,
+                    channel,
+                    &sender,
+                    private,
+                    state.clone(),
+                    &mut **storage,
+                    &callbacks,
+                );
+                // Menus that have to be downloaded first are sent later
+                if response.is_empty() {
+                    return Ok(());
                 }
+                send_reply(&irc_client, reply_to, callbacks.requester, &response);
             }
             _ => (),
         }
         Ok(())
     });
+    // The reminder timer never finishes, so a closed connection has to stop the reactor
+    reactor.register_future(messages.and_then(|()| {
+        Err(IrcError::Io(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "the server closed the connection",
+        )))
+    }));
     reactor.run()?;
 
     Ok(())
 }
 
+fn run() -> Result<(), Error> {
+    let settings = load_settings()?;
+    info!("Configuration: {:?}", settings);
+
+    // Local time is used everywhere, so this has to happen before anything asks for it
+    if let Some(ref tz) = settings.timezone {
+        std::env::set_var("TZ", tz);
+    }
+    let session = Session {
+        channels: settings.channels.clone(),
+        reminder_minutes: settings.reminder_minutes,
+        expiry_policy: settings.expiry_policy,
+        prefixes: settings.command_prefixes.clone(),
+        sasl: settings.sasl.clone(),
+        menus: Arc::new(Mutex::new(Menus::new(settings.menus.clone()))),
+        relay: match settings.tls_client_cert {
+            Some(ref client_cert) => Some(Arc::new(Relay::new(
+                &settings.server,
+                settings.port,
+                settings.tls_ca_cert.as_ref().map(|path| path.as_path()),
+                client_cert,
+            )?)),
+            None => None,
+        },
+    };
+
+    let mut states = ChannelStates::new();
+
+    let generations = settings.backup_generations;
+    let files = (settings.database_file, settings.backup_file);
+    let mut storage: Box<dyn Storage + Send> = match files {
+        (Some(ref file_name), _) => Box::new(Sqlite::open(file_name)?),
+        (None, Some(ref file_name)) => Box::new(JsonFile::new(file_name, generations)),
+        (None, None) => Box::new(NoStorage),
+    };
+    if let Err(e) = storage.load(&mut states) {
+        error!("Failed to recover state: {}", e);
+    }
+    for channel in &settings.channels {
+        states.get_or_insert(channel);
+    }
+    states.set_admins(settings.admins.clone());
+    states.set_recent_days(settings.recent_days);
+    // Without any prefix the bot is only addressed by its nick
+    states.set_prefix(match settings.command_prefixes.first() {
+        Some(prefix) => prefix.clone(),
+        None => format!("{}:", settings.nick),
+    });
+
+    let server = settings.server.clone();
+    // The irc crate joins these channels again on every connection
+    let config = Config {
+        nickname: Some(settings.nick),
+        server: Some(settings.server),
+        channels: Some(settings.channels),
+        port: Some(settings.port),
+        use_ssl: Some(settings.use_tls),
+        cert_path: settings
+            .tls_ca_cert
+            .map(|path| path.to_string_lossy().into_owned()),
+        password: settings.server_password.map(|secret| secret.0),
+        // Sent to NickServ by the irc crate once the server is done with the MOTD
+        nick_password: settings.nickserv_password.map(|secret| secret.0),
+        ..Default::default()
+    };
+
+    let state = Arc::new(Mutex::new(states));
+    let storage = Arc::new(Mutex::new(storage));
+    let mut backoff = Backoff::new(RECONNECT_DELAY, MAX_RECONNECT_DELAY);
+    loop {
+        let connected_at = Instant::now();
+        match run_session(&config, session.clone(), state.clone(), storage.clone()) {
+            Ok(()) => info!("Disconnected from {}", server),
+            Err(e) => error!("Connection to {} failed: {}", server, e),
+        }
+
+        if connected_at.elapsed() >= STABLE_CONNECTION {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        info!("Reconnecting in {} seconds", delay.as_secs());
+        thread::sleep(delay);
+    }
+}
+
 fn main() {
     // Set up logging
     env_logger::init();
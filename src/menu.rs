@@ -0,0 +1,401 @@
+use chrono::NaiveDate;
+use failure::{err_msg, Error};
+use futures::future::{self, Either};
+use futures::sync::oneshot;
+use futures::{Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::header::Location;
+use hyper::{Client, Uri};
+use hyper_tls::HttpsConnector;
+use places::normalize;
+use regex::Regex;
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Slow restaurant pages must not keep the menu waiting forever
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Pages that moved are followed this many times
+const MAX_REDIRECTS: u32 = 5;
+/// Longer menus are cut, so that the bot does not flood the channel
+const MAX_DISHES: usize = 15;
+
+/// Regular expression compared by its source, so that settings containing it can be compared
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+/// Where the daily menu of a place comes from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MenuSource {
+    /// Every match of `pattern` in the page is a dish, its first group if it has one
+    Html { url: Uri, pattern: Pattern },
+    /// Strings at the JSON pointer, or the `field` of the objects there
+    Json {
+        url: Uri,
+        pointer: String,
+        field: Option<String>,
+    },
+    /// A dish on every line, for places that only have their menu on paper
+    File(PathBuf),
+}
+
+/// Menus are downloaded over http or https only
+pub fn parse_url(url: &str) -> Result<Uri, String> {
+    let uri: Uri = url.parse().map_err(|e| format!("`{}` is not a valid URL: {}", url, e))?;
+    match uri.scheme() {
+        Some("http") | Some("https") => (),
+        _ => return Err(format!("`{}` is not an http or https URL", url)),
+    }
+    if uri.host().map_or(true, str::is_empty) {
+        return Err(format!("`{}` has no host", url));
+    }
+    Ok(uri)
+}
+
+/// Locations of redirects can be relative to the page that redirects
+fn redirect_target(from: &Uri, location: &str) -> Result<Uri, Error> {
+    let scheme = from.scheme().unwrap_or("http");
+    let authority = from.authority().unwrap_or("");
+    let has_scheme = location.find("://").map_or(false, |end| {
+        location[..end]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    let location = if has_scheme {
+        location.to_string()
+    } else if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else {
+        // Relative to the directory of the page, like a link on it
+        let path = from.path();
+        let directory = &path[..path.rfind('/').map_or(0, |slash| slash + 1)];
+        let directory = if directory.is_empty() { "/" } else { directory };
+        format!("{}://{}{}{}", scheme, authority, directory, location)
+    };
+    parse_url(&location).map_err(|e| format_err!("the page moved to {}", e))
+}
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Body of the page at `url`, redirects are followed up to `redirects` times
+fn follow(
+    client: HttpClient,
+    url: Uri,
+    redirects: u32,
+) -> Box<dyn Future<Item = String, Error = Error>> {
+    let response = client.get(url.clone()).map_err(Error::from);
+    Box::new(response.and_then(move |response| {
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response.headers().get::<Location>().map(|l| l.to_string());
+            let target = match location {
+                _ if redirects == 0 => Err(format_err!("the page redirects too many times")),
+                Some(location) => redirect_target(&url, &location),
+                None => Err(format_err!("the server answered `{}` without a location", status)),
+            };
+            return match target {
+                Ok(target) => Either::A(follow(client, target, redirects - 1)),
+                Err(e) => Either::B(Either::A(future::err(e))),
+            };
+        }
+        if !status.is_success() {
+            let error = format_err!("the server answered `{}`", status);
+            return Either::B(Either::A(future::err(error)));
+        }
+        let body = response
+            .body()
+            .concat2()
+            .map_err(Error::from)
+            .map(|body| String::from_utf8_lossy(&body).into_owned());
+        Either::B(Either::B(body))
+    }))
+}
+
+/// Downloads the page at `url` on the reactor of `handle`
+fn get(url: &Uri, handle: &Handle) -> Box<dyn Future<Item = String, Error = Error>> {
+    // The connector resolves host names on a thread of its own
+    let client = match HttpsConnector::new(1, handle) {
+        Ok(connector) => Client::configure().connector(connector).build(handle),
+        Err(e) => return Box::new(future::err(Error::from(e))),
+    };
+    let response = follow(client, url.clone(), MAX_REDIRECTS);
+
+    let timeout = match Timeout::new(FETCH_TIMEOUT, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(Error::from(e))),
+    };
+    let timeout = timeout
+        .map_err(Error::from)
+        .and_then(|()| Err(format_err!("the page did not load in time")));
+    Box::new(response.select(timeout).map(|(body, _)| body).map_err(|(e, _)| e))
+}
+
+/// Reads the file on a thread of its own, so that a slow disk does not stall the reactor
+fn read_file(path: &Path) -> Box<dyn Future<Item = String, Error = Error>> {
+    let (sender, receiver) = oneshot::channel();
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let mut contents = String::new();
+        let read = File::open(&path).and_then(|mut f| f.read_to_string(&mut contents));
+        // Nobody is waiting any more when the reactor stopped
+        let _ = sender.send(read.map(|_| contents).map_err(Error::from));
+    });
+    Box::new(
+        receiver
+            .map_err(|_| err_msg("reading the menu was cancelled"))
+            .and_then(|contents| contents),
+    )
+}
+
+/// Text of an HTML fragment: tags are dropped, common entities decoded and whitespace collapsed
+fn html_text(html: &str) -> String {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+    }
+    let text = TAG
+        .replace_all(html, " ")
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Dishes found in the downloaded page or the file, empty ones are skipped
+pub fn dishes(source: &MenuSource, body: &str) -> Result<Vec<String>, Error> {
+    let dishes: Vec<String> = match *source {
+        MenuSource::Html { ref pattern, .. } => pattern
+            .0
+            .captures_iter(body)
+            .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .map(|m| html_text(m.as_str()))
+            .collect(),
+        MenuSource::Json {
+            ref pointer,
+            ref field,
+            ..
+        } => {
+            let json: Value = serde_json::from_str(body)?;
+            let items = json
+                .pointer(pointer)
+                .and_then(Value::as_array)
+                .ok_or_else(|| format_err!("there is no list at `{}`", pointer))?;
+            items
+                .iter()
+                .filter_map(|item| match *field {
+                    Some(ref field) => item.get(field),
+                    None => Some(item),
+                })
+                .filter_map(|item| match *item {
+                    Value::String(ref dish) => Some(dish.clone()),
+                    Value::Number(ref number) => Some(number.to_string()),
+                    _ => None,
+                })
+                .collect()
+        }
+        MenuSource::File(_) => body.lines().map(|line| line.trim().to_string()).collect(),
+    };
+    Ok(dishes.into_iter().filter(|dish| !dish.is_empty()).collect())
+}
+
+/// Downloads or reads the menu and picks the dishes out of it
+pub fn fetch(
+    source: &MenuSource,
+    handle: &Handle,
+) -> Box<dyn Future<Item = Vec<String>, Error = Error>> {
+    let body = match *source {
+        MenuSource::Html { ref url, .. } | MenuSource::Json { ref url, .. } => get(url, handle),
+        MenuSource::File(ref path) => read_file(path),
+    };
+    let source = source.clone();
+    Box::new(body.and_then(move |body| dishes(&source, &body)))
+}
+
+pub fn format_menu(place: &str, dishes: &[String]) -> String {
+    if dishes.is_empty() {
+        return format!("The menu of {} is empty today", place);
+    }
+    let mut lines = vec![format!("Menu of {} today:", place)];
+    lines.extend(dishes.iter().take(MAX_DISHES).map(|dish| format!("- {}", dish)));
+    if dishes.len() > MAX_DISHES {
+        lines.push(format!("... and {} more", dishes.len() - MAX_DISHES));
+    }
+    lines.join("\r\n")
+}
+
+/// Menu sources from the configuration and the menus fetched today
+#[derive(Debug, Default)]
+pub struct Menus {
+    sources: Vec<(String, MenuSource)>,
+    cache: HashMap<String, (NaiveDate, Vec<String>)>,
+}
+
+impl Menus {
+    pub fn new(sources: Vec<(String, MenuSource)>) -> Menus {
+        Menus {
+            sources,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn source(&self, place: &str) -> Option<&MenuSource> {
+        let place = normalize(place);
+        self.sources
+            .iter()
+            .find(|&&(ref name, _)| normalize(name) == place)
+            .map(|&(_, ref source)| source)
+    }
+
+    pub fn unknown(&self, place: &str) -> String {
+        let names: Vec<&str> = self.sources.iter().map(|&(ref name, _)| name.as_str()).collect();
+        if names.is_empty() {
+            return "No menus are configured".to_string();
+        }
+        format!("There is no menu of {}, try {}", place, names.join(", "))
+    }
+
+    /// Menus are fetched once a day, restaurants rarely change them before lunch
+    pub fn cached(&self, place: &str, today: NaiveDate) -> Option<&[String]> {
+        match self.cache.get(&normalize(place)) {
+            Some(&(date, ref dishes)) if date == today => Some(dishes),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, place: &str, today: NaiveDate, dishes: Vec<String>) {
+        self.cache.insert(normalize(place), (today, dishes));
+    }
+}
+
+#[cfg(test)]
+use std::net::TcpListener;
+#[cfg(test)]
+use tokio_core::reactor::Core;
+
+/// Answers a request on a new connection with each of the `responses` and returns the address
+/// to send them to
+#[cfg(test)]
+fn serve(responses: &'static [&'static str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    format!("http://{}/menu", address)
+}
+
+#[test]
+fn fetch_html_menu() {
+    let url = serve(&[
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n\
+         <ul><li class=\"dish\">Svíčková  na&nbsp;smetaně</li>\
+         <li class=\"dish\"><b>Fish &amp; chips</b></li><li>Coffee</li></ul>",
+    ]);
+    let source = MenuSource::Html {
+        url: parse_url(&url).unwrap(),
+        pattern: Pattern(Regex::new(r#"<li class="dish">(.*?)</li>"#).unwrap()),
+    };
+    let mut core = Core::new().unwrap();
+    let dishes = core.run(fetch(&source, &core.handle())).unwrap();
+    assert_eq!(dishes, vec!["Svíčková na smetaně", "Fish & chips"]);
+}
+
+#[test]
+fn fetch_json_menu() {
+    let url = serve(&[
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: /menu/today\r\nContent-Length: 0\r\n\
+         Connection: close\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+         f\r\n{\"today\": [{\"na\r\n\
+         1d\r\nme\": \"Pho bo\"}, {\"name\": \"Bun\r\n\
+         8\r\n cha\"}]}\r\n\
+         0\r\n\r\n",
+    ]);
+    let source = MenuSource::Json {
+        url: parse_url(&url).unwrap(),
+        pointer: "/today".to_string(),
+        field: Some("name".to_string()),
+    };
+    let mut core = Core::new().unwrap();
+    let dishes = core.run(fetch(&source, &core.handle())).unwrap();
+    assert_eq!(dishes, vec!["Pho bo", "Bun cha"]);
+}
+
+#[test]
+fn failed_requests_are_reported() {
+    let url = serve(&["HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"]);
+    let source = MenuSource::Json {
+        url: parse_url(&url).unwrap(),
+        pointer: "/today".to_string(),
+        field: None,
+    };
+    let mut core = Core::new().unwrap();
+    let error = core.run(fetch(&source, &core.handle())).unwrap_err();
+    assert_eq!(error.to_string(), "the server answered `404 Not Found`");
+}
+
+#[test]
+fn menus_are_cached_for_a_day() {
+    let mut menus = Menus::new(vec![(
+        "U Kulečníku".to_string(),
+        MenuSource::File(PathBuf::from("/srv/menus/kulecnik.txt")),
+    )]);
+    assert!(menus.source("u kulečníku").is_some());
+    assert_eq!(menus.unknown("Winston"), "There is no menu of Winston, try U Kulečníku");
+
+    let today = NaiveDate::from_ymd(2018, 7, 31);
+    menus.store("U Kulečníku", today, vec!["Guláš".to_string()]);
+    assert_eq!(menus.cached("u kulečníku", today), Some(&["Guláš".to_string()][..]));
+    assert_eq!(menus.cached("U Kulečníku", today.succ()), None);
+}
+
+#[test]
+fn menu_urls() {
+    assert!(parse_url("https://example.com").is_ok());
+    assert!(parse_url("ftp://example.com/menu").is_err());
+    assert!(parse_url("/menu").is_err());
+    let page = parse_url("http://localhost:8080/menu?day=1").unwrap();
+    assert_eq!(
+        redirect_target(&page, "/today").unwrap().to_string(),
+        "http://localhost:8080/today"
+    );
+    assert_eq!(
+        redirect_target(&page, "https://example.com/today").unwrap().to_string(),
+        "https://example.com/today"
+    );
+    assert_eq!(
+        redirect_target(&page, "//example.com/today").unwrap().to_string(),
+        "http://example.com/today"
+    );
+    let page = parse_url("http://localhost:8080/menus/week?day=1").unwrap();
+    assert_eq!(
+        redirect_target(&page, "today?from=http://localhost:8080/").unwrap().to_string(),
+        "http://localhost:8080/menus/today?from=http://localhost:8080/"
+    );
+}
@@ -0,0 +1,190 @@
+use chrono::NaiveTime;
+use failure::Error;
+use serde_json::{Map, Value};
+use time::{parse_time, LunchTime};
+
+/// Version written into new backups
+pub const CURRENT_VERSION: u64 = 1;
+
+/// Step `n` turns a backup of version `n` into version `n + 1`
+const MIGRATIONS: &'static [fn(Value) -> Result<Value, Error>] = &[single_channel_to_channels];
+
+/// Backups without a version field are either a single channel state (0) or a map of channel
+/// states written before versions were introduced (1)
+fn version_of(value: &Value) -> u64 {
+    match value.get("version").and_then(Value::as_u64) {
+        Some(version) => version,
+        None if value.get("channels").is_some() => 1,
+        None => 0,
+    }
+}
+
+/// Upgrades a parsed backup of any known version to the current format
+pub fn migrate(mut value: Value) -> Result<Value, Error> {
+    let version = version_of(&value);
+    if version > CURRENT_VERSION {
+        return Err(format_err!(
+            "backup version {} is newer than the supported version {}",
+            version,
+            CURRENT_VERSION
+        ));
+    }
+    for (n, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        value = step(value)?;
+        info!("Migrated the backup from version {} to {}", n, n + 1);
+    }
+    Ok(value)
+}
+
+/// Times used to be stored as typed by the user ("12.30"), later as "12:30:00"
+fn migrate_time(time: &Value) -> Option<Value> {
+    let time = time.as_str()?;
+    if NaiveTime::parse_from_str(time, "%H:%M:%S%.f").is_ok() {
+        return Some(Value::from(time));
+    }
+    match parse_time(time) {
+        Ok(LunchTime::At(time)) => Some(Value::from(time.format("%H:%M:%S").to_string())),
+        _ => None,
+    }
+}
+
+/// Fixes up proposals from before they had ids and parsed times
+fn migrate_proposals(state: &mut Map<String, Value>) -> Result<(), Error> {
+    let mut last_id = state
+        .get("last_proposal_id")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let proposals = match state.get_mut("proposals").and_then(Value::as_array_mut) {
+        Some(proposals) => proposals,
+        None => return Err(format_err!("the backup has no proposals")),
+    };
+    last_id = proposals
+        .iter()
+        .filter_map(|p| p.get("id").and_then(Value::as_u64))
+        .fold(last_id, u64::max);
+
+    let mut migrated = vec![];
+    for mut proposal in proposals.drain(..) {
+        let time = proposal.get("time").and_then(migrate_time);
+        let proposal_map = match (proposal.as_object_mut(), time) {
+            (Some(map), Some(time)) => {
+                map.insert("time".to_string(), time);
+                map
+            }
+            _ => {
+                warn!("Dropping proposal {} with a time that cannot be migrated", proposal);
+                continue;
+            }
+        };
+
+        let meeting_point = proposal_map
+            .get("meeting_point")
+            .and_then(Value::as_array)
+            .and_then(|point| match (point.get(0), point.get(1).and_then(migrate_time)) {
+                (Some(place), Some(time)) => Some(Value::from(vec![place.clone(), time])),
+                _ => None,
+            });
+        proposal_map.insert(
+            "meeting_point".to_string(),
+            meeting_point.unwrap_or(Value::Null),
+        );
+
+        if proposal_map.get("id").and_then(Value::as_u64).unwrap_or(0) == 0 {
+            last_id += 1;
+            proposal_map.insert("id".to_string(), Value::from(last_id));
+        }
+        migrated.push(proposal);
+    }
+    *proposals = migrated;
+    state.insert("last_proposal_id".to_string(), Value::from(last_id));
+    Ok(())
+}
+
+/// Version 0 held the state of the only channel the bot could join
+fn single_channel_to_channels(mut state: Value) -> Result<Value, Error> {
+    let channel = match state.get("channel").and_then(Value::as_str) {
+        Some(channel) => channel.to_string(),
+        None => return Err(format_err!("the backup has no channel")),
+    };
+    if let Some(map) = state.as_object_mut() {
+        migrate_proposals(map)?;
+    }
+
+    let mut channels = Map::new();
+    channels.insert(channel, state);
+    let mut states = Map::new();
+    states.insert("channels".to_string(), Value::Object(channels));
+    states.insert("version".to_string(), Value::from(1));
+    Ok(Value::Object(states))
+}
+
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use state::ChannelStates;
+
+/// Migrates the backup and checks that the result can be loaded
+#[cfg(test)]
+fn load(json: &str) -> Result<Value, Error> {
+    let value = migrate(serde_json::from_str(json)?)?;
+    serde_json::from_value::<ChannelStates>(value.clone())?;
+    Ok(value)
+}
+
+#[test]
+fn load_original_backup() {
+    // Free-form times and no ids
+    let states = load(
+        r##"{"groups": [{"name": "coreserv1", "users": ["jan", "ondra"]}],
+             "proposals": [
+               {"place": "winston", "time": "12.30", "group": "coreserv1",
+                "meeting_point": ["lobby", "12:25"],
+                "created": {"secs_since_epoch": 1530000000, "nanos_since_epoch": 0}},
+               {"place": "kocka", "time": "whenever", "group": null, "meeting_point": null,
+                "created": {"secs_since_epoch": 1530000000, "nanos_since_epoch": 0}}
+             ],
+             "store": 0, "channel": "#lunch"}"##,
+    ).unwrap();
+
+    let state = &states["channels"]["#lunch"];
+    assert_eq!(states["version"], 1);
+    assert_eq!(state["groups"][0]["name"], "coreserv1");
+    assert_eq!(state["proposals"].as_array().unwrap().len(), 1);
+    assert_eq!(state["proposals"][0]["id"], 1);
+    assert_eq!(state["proposals"][0]["time"], "12:30:00");
+    assert_eq!(state["proposals"][0]["meeting_point"][1], "12:25:00");
+    assert_eq!(state["last_proposal_id"], 1);
+}
+
+#[test]
+fn load_backup_with_attendees_and_ids() {
+    let states = load(
+        r##"{"groups": [],
+             "proposals": [
+               {"id": 7, "place": "winston", "time": "12:00:00", "group": null,
+                "meeting_point": null, "attendees": ["jan"],
+                "created": {"secs_since_epoch": 1530000000, "nanos_since_epoch": 0}},
+               {"place": "kocka", "time": "11:45:00", "group": null, "meeting_point": null,
+                "created": {"secs_since_epoch": 1530000000, "nanos_since_epoch": 0}}
+             ],
+             "store": 0, "channel": "#lunch", "last_proposal_id": 7}"##,
+    ).unwrap();
+
+    let state = &states["channels"]["#lunch"];
+    assert_eq!(state["proposals"][0]["id"], 7);
+    assert_eq!(state["proposals"][0]["attendees"][0], "jan");
+    assert_eq!(state["proposals"][1]["id"], 8);
+    assert_eq!(state["last_proposal_id"], 8);
+}
+
+#[test]
+fn load_unversioned_channels_backup() {
+    let json = r##"{"channels": {"#lunch": {"groups": [], "proposals": [], "store": 0,
+                    "channel": "#lunch", "last_proposal_id": 3}}}"##;
+    assert_eq!(load(json).unwrap(), serde_json::from_str::<Value>(json).unwrap());
+}
+
+#[test]
+fn reject_newer_backup() {
+    assert!(load(r##"{"version": 99, "channels": {}}"##).is_err());
+}
@@ -0,0 +1,114 @@
+/// Who sent a command. Anybody can take a nick, so permissions are checked against the services
+/// account or the hostmask instead.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Identity {
+    pub nick: String,
+    /// Account the sender is logged in to, servers tell it with the account-tag capability
+    pub account: Option<String>,
+    /// `nick!user@host`
+    pub hostmask: Option<String>,
+}
+
+impl Identity {
+    /// Sender known only by the nick, it matches no account or hostmask
+    #[cfg(test)]
+    pub fn new(nick: &str) -> Identity {
+        Identity {
+            nick: nick.to_string(),
+            account: None,
+            hostmask: None,
+        }
+    }
+
+    /// A pattern with `!` or `@` is a hostmask with `*` and `?` wildcards, anything else is an
+    /// account name
+    pub fn matches(&self, pattern: &str) -> bool {
+        if pattern.contains('!') || pattern.contains('@') {
+            self.hostmask
+                .as_ref()
+                .map_or(false, |mask| wildcard_match(pattern, mask))
+        } else {
+            self.account
+                .as_ref()
+                .map_or(false, |account| account.to_lowercase() == pattern.to_lowercase())
+        }
+    }
+
+    /// Pattern that matches the sender even after a nick change, None when nothing but the nick
+    /// is known. A user name starting with `~` was not verified by identd and anybody on the host
+    /// can pick it, so only the host is matched then.
+    pub fn pattern(&self) -> Option<String> {
+        match (&self.account, &self.hostmask) {
+            (&Some(ref account), _) => Some(account.clone()),
+            (&None, &Some(ref mask)) => mask.find('!').map(|i| {
+                let user_host = &mask[i + 1..];
+                if user_host.starts_with('~') {
+                    let host = user_host.find('@').map_or("", |at| &user_host[at + 1..]);
+                    format!("*!*@{}", host)
+                } else {
+                    format!("*!{}", user_host)
+                }
+            }),
+            (&None, &None) => None,
+        }
+    }
+}
+
+/// Case insensitive match where `*` stands for any number of characters and `?` for one
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to go back to in the pattern and the text when the characters after a star do not
+    // match, the star then swallows one more character
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(&'*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after_star, swallowed)) => {
+                    star = Some((after_star, swallowed + 1));
+                    p = after_star;
+                    t = swallowed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn hostmasks_and_accounts() {
+    let jan = Identity {
+        nick: "jan|wfh".to_string(),
+        account: None,
+        hostmask: Some("jan|wfh!~jan@vpn-12.Example.com".to_string()),
+    };
+    assert!(jan.matches("*!~jan@*.example.com"));
+    assert!(jan.matches("jan*!*@vpn-??.example.com"));
+    assert!(!jan.matches("*!~jan@example.com"));
+    assert!(!jan.matches("jan"));
+    assert_eq!(jan.pattern(), Some("*!*@vpn-12.Example.com".to_string()));
+    let identified = Identity {
+        hostmask: Some("jan!jan@vpn-12.example.com".to_string()),
+        ..jan.clone()
+    };
+    assert_eq!(identified.pattern(), Some("*!jan@vpn-12.example.com".to_string()));
+
+    let logged_in = Identity {
+        account: Some("Jan".to_string()),
+        ..jan
+    };
+    assert!(logged_in.matches("jan"));
+    assert_eq!(logged_in.pattern(), Some("Jan".to_string()));
+    assert!(!Identity::new("jan").matches("jan"));
+    assert_eq!(Identity::new("jan").pattern(), None);
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+use syntax::{did_you_mean, edit_distance};
+
+/// A restaurant known under a canonical name and any number of aliases
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub address: Option<String>,
+    pub walking_minutes: Option<u32>,
+    pub notes: Option<String>,
+    /// What the place is good for, e.g. vegetarian, matched against the diets of users
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Pattern matching whoever added the place, see `Identity::pattern`
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+impl Place {
+    pub fn new<T: Into<String>>(name: T) -> Place {
+        Place {
+            name: name.into(),
+            aliases: vec![],
+            address: None,
+            walking_minutes: None,
+            notes: None,
+            tags: vec![],
+            owner: None,
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = normalize(tag);
+        self.tags.iter().any(|t| normalize(t) == tag)
+    }
+
+    fn names<'a>(&'a self) -> impl Iterator<Item = &'a str> {
+        Some(self.name.as_str())
+            .into_iter()
+            .chain(self.aliases.iter().map(String::as_str))
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.aliases.is_empty() {
+            write!(f, " (also {})", self.aliases.join(", "))?;
+        }
+        if let Some(ref address) = self.address {
+            write!(f, " - {}", address)?;
+        }
+        if let Some(minutes) = self.walking_minutes {
+            write!(f, " - {} min walk", minutes)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " - [{}]", self.tags.join(", "))?;
+        }
+        if let Some(ref notes) = self.notes {
+            write!(f, " - {}", notes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Case, quotes and extra spaces do not make a different place: 'Winston' is winston
+pub fn normalize(name: &str) -> String {
+    name.trim_matches(|c: char| !c.is_alphanumeric())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Restaurants of a channel, places of proposals are resolved against it
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct Catalogue {
+    places: Vec<Place>,
+}
+
+impl Catalogue {
+    pub fn places(&self) -> &[Place] {
+        &self.places
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        let name = normalize(name);
+        self.places
+            .iter()
+            .position(|p| p.names().any(|n| normalize(n) == name))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Place> {
+        self.position(name).map(|i| &self.places[i])
+    }
+
+    /// Finds the place by its name or alias, small typos in longer names are forgiven
+    pub fn resolve(&self, name: &str) -> Option<&Place> {
+        let normalized = normalize(name);
+        let max_distance = normalized.chars().count() / 4;
+        self.find(name).or_else(|| {
+            self.places
+                .iter()
+                .flat_map(|p| p.names().map(move |n| (p, normalize(n))))
+                .map(|(p, n)| (edit_distance(&normalized, &n), p))
+                .filter(|&(d, _)| d <= max_distance)
+                .min_by_key(|&(d, _)| d)
+                .map(|(_, p)| p)
+        })
+    }
+
+    /// Canonical name of a known place, other places stay as they were written
+    pub fn canonical(&self, name: &str) -> String {
+        match self.resolve(name) {
+            Some(place) => place.name.clone(),
+            None => name.to_string(),
+        }
+    }
+
+    pub fn unknown_place(&self, name: &str) -> String {
+        match did_you_mean(name, self.places.iter().map(|p| p.name.as_str())) {
+            Some(similar) => format!("unknown place `{}`, did you mean `{}`?", name, similar),
+            None => format!("unknown place `{}`", name),
+        }
+    }
+
+    fn taken(&self, name: &str) -> Result<(), String> {
+        match self.find(name) {
+            Some(place) => Err(format!("`{}` is already used for {}", name, place.name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn add(&mut self, place: Place) -> Result<&Place, String> {
+        self.taken(&place.name)?;
+        self.places.push(place);
+        Ok(&self.places[self.places.len() - 1])
+    }
+
+    pub fn alias(&mut self, name: &str, alias: String) -> Result<&Place, String> {
+        self.taken(&alias)?;
+        let i = self.position(name).ok_or_else(|| self.unknown_place(name))?;
+        self.places[i].aliases.push(alias);
+        Ok(&self.places[i])
+    }
+
+    pub fn tag(&mut self, name: &str, tags: Vec<String>) -> Result<&Place, String> {
+        let i = self.position(name).ok_or_else(|| self.unknown_place(name))?;
+        self.places[i].tags = tags;
+        Ok(&self.places[i])
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Place, String> {
+        match self.position(name) {
+            Some(position) => Ok(self.places.remove(position)),
+            None => Err(self.unknown_place(name)),
+        }
+    }
+
+    pub fn info(&self, name: &str) -> String {
+        match self.resolve(name) {
+            Some(place) => place.to_string(),
+            None => self.unknown_place(name),
+        }
+    }
+}
+
+#[cfg(test)]
+fn catalogue() -> Catalogue {
+    let mut catalogue = Catalogue::default();
+    catalogue.add(Place::new("Winston")).unwrap();
+    catalogue.add(Place::new("U Kulečníku")).unwrap();
+    catalogue.add(Place::new("Pho")).unwrap();
+    catalogue.alias("u kulečníku", "kulec".to_string()).unwrap();
+    catalogue
+}
+
+#[test]
+fn places_are_resolved_by_name_and_alias() {
+    let catalogue = catalogue();
+    assert_eq!(catalogue.canonical("winston"), "Winston");
+    assert_eq!(catalogue.canonical("'Winston'"), "Winston");
+    assert_eq!(catalogue.canonical("KULEC"), "U Kulečníku");
+    assert_eq!(catalogue.canonical("u kulecniku"), "U Kulečníku");
+    assert_eq!(catalogue.canonical("winstn"), "Winston");
+    // Short names are too easy to confuse
+    assert_eq!(catalogue.canonical("pub"), "pub");
+    assert_eq!(catalogue.canonical("kocka"), "kocka");
+}
+
+#[test]
+fn names_and_aliases_are_unique() {
+    let mut catalogue = catalogue();
+    assert_eq!(
+        catalogue.add(Place::new("winston")),
+        Err("`winston` is already used for Winston".to_string())
+    );
+    assert_eq!(
+        catalogue.alias("pho", "Kulec".to_string()),
+        Err("`Kulec` is already used for U Kulečníku".to_string())
+    );
+    assert_eq!(
+        catalogue.alias("wnston", "win".to_string()),
+        Err("unknown place `wnston`, did you mean `Winston`?".to_string())
+    );
+    assert_eq!(catalogue.remove("kulec").unwrap().name, "U Kulečníku");
+    assert_eq!(catalogue.canonical("kulec"), "kulec");
+}
+
+#[test]
+fn place_info() {
+    let mut catalogue = catalogue();
+    let mut place = Place::new("Kocka");
+    place.address = Some("Vinohradská 12".to_string());
+    place.walking_minutes = Some(5);
+    place.notes = Some("cash only".to_string());
+    catalogue.add(place).unwrap();
+    catalogue.alias("kocka", "cat".to_string()).unwrap();
+    assert_eq!(
+        catalogue.info("cat"),
+        "Kocka (also cat) - Vinohradská 12 - 5 min walk - cash only"
+    );
+    catalogue.tag("cat", vec!["vegan".to_string(), "Gluten free".to_string()]).unwrap();
+    assert!(catalogue.resolve("kocka").unwrap().has_tag("Vegan"));
+    assert_eq!(
+        catalogue.info("cat"),
+        "Kocka (also cat) - Vinohradská 12 - 5 min walk - [vegan, Gluten free] - cash only"
+    );
+}
@@ -0,0 +1,782 @@
+use chrono::{NaiveDate, NaiveTime};
+use failure::{err_msg, Error};
+use places::{Catalogue, Place};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Transaction, NO_PARAMS};
+use state::{ChannelStates, Group, Lunch, LunchBotState, Poll, Preferences, Proposal};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use storage::Storage;
+
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS channels (
+        name TEXT PRIMARY KEY,
+        store INTEGER NOT NULL,
+        last_proposal_id INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS groups (
+        channel TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        PRIMARY KEY (channel, position)
+    );
+    CREATE TABLE IF NOT EXISTS group_members (
+        channel TEXT NOT NULL,
+        group_position INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        nick TEXT NOT NULL,
+        PRIMARY KEY (channel, group_position, position)
+    );
+    CREATE TABLE IF NOT EXISTS group_owners (
+        channel TEXT NOT NULL,
+        group_position INTEGER NOT NULL,
+        owner TEXT NOT NULL,
+        PRIMARY KEY (channel, group_position)
+    );
+    CREATE TABLE IF NOT EXISTS proposals (
+        channel TEXT NOT NULL,
+        id INTEGER NOT NULL,
+        place TEXT NOT NULL,
+        time TEXT NOT NULL,
+        group_name TEXT,
+        meeting_place TEXT,
+        meeting_time TEXT,
+        created_secs INTEGER NOT NULL,
+        created_nanos INTEGER NOT NULL,
+        reminded INTEGER NOT NULL,
+        meeting_reminded INTEGER NOT NULL,
+        PRIMARY KEY (channel, id)
+    );
+    CREATE TABLE IF NOT EXISTS attendees (
+        channel TEXT NOT NULL,
+        proposal_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        nick TEXT NOT NULL,
+        PRIMARY KEY (channel, proposal_id, position)
+    );
+    CREATE TABLE IF NOT EXISTS proposal_owners (
+        channel TEXT NOT NULL,
+        proposal_id INTEGER NOT NULL,
+        owner TEXT NOT NULL,
+        PRIMARY KEY (channel, proposal_id)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        channel TEXT NOT NULL,
+        proposal_id INTEGER NOT NULL,
+        place TEXT NOT NULL,
+        date TEXT NOT NULL,
+        time TEXT NOT NULL,
+        group_name TEXT,
+        created_secs INTEGER NOT NULL,
+        archived_secs INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history_attendees (
+        history_id INTEGER NOT NULL,
+        nick TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS places (
+        channel TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        address TEXT,
+        walking_minutes INTEGER,
+        notes TEXT,
+        PRIMARY KEY (channel, position)
+    );
+    CREATE TABLE IF NOT EXISTS place_aliases (
+        channel TEXT NOT NULL,
+        place_position INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        alias TEXT NOT NULL,
+        PRIMARY KEY (channel, place_position, position)
+    );
+    CREATE TABLE IF NOT EXISTS place_tags (
+        channel TEXT NOT NULL,
+        place_position INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (channel, place_position, position)
+    );
+    CREATE TABLE IF NOT EXISTS place_owners (
+        channel TEXT NOT NULL,
+        place_position INTEGER NOT NULL,
+        owner TEXT NOT NULL,
+        PRIMARY KEY (channel, place_position)
+    );
+    CREATE TABLE IF NOT EXISTS vetoes (
+        nick TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        place TEXT NOT NULL,
+        PRIMARY KEY (nick, position)
+    );
+    CREATE TABLE IF NOT EXISTS subscriptions (
+        nick TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS polls (
+        channel TEXT PRIMARY KEY,
+        deadline TEXT NOT NULL,
+        time TEXT NOT NULL,
+        group_name TEXT,
+        created_secs INTEGER NOT NULL,
+        created_nanos INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS poll_places (
+        channel TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        place TEXT NOT NULL,
+        PRIMARY KEY (channel, position)
+    );
+    CREATE TABLE IF NOT EXISTS poll_votes (
+        channel TEXT NOT NULL,
+        nick TEXT NOT NULL,
+        choice INTEGER NOT NULL,
+        PRIMARY KEY (channel, nick)
+    );
+    CREATE TABLE IF NOT EXISTS diets (
+        nick TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (nick, position)
+    );
+";
+
+const TIME_FORMAT: &'static str = "%H:%M:%S";
+const DATE_FORMAT: &'static str = "%Y-%m-%d";
+
+/// Stores groups and proposals as rows in an SQLite database and keeps expired proposals in the
+/// `history` table
+pub struct Sqlite {
+    connection: Connection,
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, Error> {
+    Ok(NaiveTime::parse_from_str(time, TIME_FORMAT)?)
+}
+
+impl Sqlite {
+    pub fn open(file_name: &Path) -> Result<Sqlite, Error> {
+        Sqlite::with_connection(Connection::open(file_name)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Sqlite, Error> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Sqlite { connection })
+    }
+
+    fn load_groups(&self, channel: &str) -> Result<Vec<Group>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT position, name FROM groups WHERE channel = ? ORDER BY position")?;
+        let rows = statement.query_map(&[channel], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut groups = vec![];
+        for row in rows {
+            let (position, name) = row?;
+            let mut members = self.connection.prepare(
+                "SELECT nick FROM group_members WHERE channel = ? AND group_position = ? \
+                 ORDER BY position",
+            )?;
+            let users = members
+                .query_map(&[&channel as &dyn ToSql, &position], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            let mut group = Group::new(name, users);
+            group.owner = self
+                .connection
+                .prepare("SELECT owner FROM group_owners WHERE channel = ? AND group_position = ?")?
+                .query_map(&[&channel as &dyn ToSql, &position], |row| row.get(0))?
+                .next()
+                .transpose()?;
+            groups.push(group);
+        }
+        Ok(groups)
+    }
+
+    fn load_proposals(&self, channel: &str) -> Result<Vec<Proposal>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, place, time, group_name, meeting_place, meeting_time, created_secs, \
+             created_nanos, reminded, meeting_reminded FROM proposals WHERE channel = ? \
+             ORDER BY id",
+        )?;
+        let rows = statement.query_map(&[channel], |row| {
+            Ok((
+                (
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ),
+                (
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ),
+                (
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, u32>(7)?,
+                    row.get::<_, bool>(8)?,
+                    row.get::<_, bool>(9)?,
+                ),
+            ))
+        })?;
+
+        let mut proposals = vec![];
+        for row in rows {
+            let ((id, place, time, group), meeting_point, flags) = row?;
+            let (secs, nanos, reminded, meeting_reminded) = flags;
+            let meeting_point = match meeting_point {
+                (Some(place), Some(time)) => Some((place, parse_time(&time)?)),
+                _ => None,
+            };
+            let mut attendees = self.connection.prepare(
+                "SELECT nick FROM attendees WHERE channel = ? AND proposal_id = ? \
+                 ORDER BY position",
+            )?;
+            let attendees = attendees
+                .query_map(&[&channel as &dyn ToSql, &id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+
+            let mut proposal = match group {
+                Some(group) => {
+                    Proposal::new_with_group(place, parse_time(&time)?, group, meeting_point)
+                }
+                None => Proposal::new(place, parse_time(&time)?, meeting_point),
+            };
+            proposal.id = id;
+            proposal.created = UNIX_EPOCH + Duration::new(secs as u64, nanos);
+            proposal.attendees = attendees;
+            proposal.reminded = reminded;
+            proposal.meeting_reminded = meeting_reminded;
+            proposal.owner = self
+                .connection
+                .prepare("SELECT owner FROM proposal_owners WHERE channel = ? AND proposal_id = ?")?
+                .query_map(&[&channel as &dyn ToSql, &id], |row| row.get(0))?
+                .next()
+                .transpose()?;
+            proposals.push(proposal);
+        }
+        Ok(proposals)
+    }
+
+    fn load_places(&self, channel: &str) -> Result<Catalogue, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT position, name, address, walking_minutes, notes FROM places \
+             WHERE channel = ? ORDER BY position",
+        )?;
+        let rows = statement.query_map(&[channel], |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<u32>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?;
+
+        let mut catalogue = Catalogue::default();
+        for row in rows {
+            let (position, name, address, walking_minutes, notes) = row?;
+            let mut aliases = self.connection.prepare(
+                "SELECT alias FROM place_aliases WHERE channel = ? AND place_position = ? \
+                 ORDER BY position",
+            )?;
+            let mut tags = self.connection.prepare(
+                "SELECT tag FROM place_tags WHERE channel = ? AND place_position = ? \
+                 ORDER BY position",
+            )?;
+            let mut place = Place::new(name);
+            place.aliases = aliases
+                .query_map(&[&channel as &dyn ToSql, &position], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            place.tags = tags
+                .query_map(&[&channel as &dyn ToSql, &position], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            place.address = address;
+            place.walking_minutes = walking_minutes;
+            place.notes = notes;
+            place.owner = self
+                .connection
+                .prepare("SELECT owner FROM place_owners WHERE channel = ? AND place_position = ?")?
+                .query_map(&[&channel as &dyn ToSql, &position], |row| row.get(0))?
+                .next()
+                .transpose()?;
+            catalogue.add(place).map_err(err_msg)?;
+        }
+        Ok(catalogue)
+    }
+
+    fn load_preferences(&self) -> Result<BTreeMap<String, Preferences>, Error> {
+        let mut preferences: BTreeMap<String, Preferences> = BTreeMap::new();
+        let mut vetoes = self
+            .connection
+            .prepare("SELECT nick, place FROM vetoes ORDER BY nick, position")?;
+        let rows = vetoes.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (nick, place) = row?;
+            preferences.entry(nick).or_default().vetoes.push(place);
+        }
+
+        let mut diets = self
+            .connection
+            .prepare("SELECT nick, tag FROM diets ORDER BY nick, position")?;
+        let rows = diets.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (nick, tag) = row?;
+            preferences.entry(nick).or_default().diet.push(tag);
+        }
+
+        let subscribers = self
+            .connection
+            .prepare("SELECT nick FROM subscriptions")?
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        for nick in subscribers {
+            preferences.entry(nick).or_default().subscribed = true;
+        }
+        Ok(preferences)
+    }
+
+    fn load_poll(&self, channel: &str) -> Result<Option<Poll>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT deadline, time, group_name, created_secs, created_nanos FROM polls \
+             WHERE channel = ?",
+        )?;
+        let mut rows = statement.query_map(&[channel], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, u32>(4)?,
+            ))
+        })?;
+        let (deadline, time, group, secs, nanos) = match rows.next() {
+            Some(row) => row?,
+            None => return Ok(None),
+        };
+
+        let places = self
+            .connection
+            .prepare("SELECT place FROM poll_places WHERE channel = ? ORDER BY position")?
+            .query_map(&[channel], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        let votes = self
+            .connection
+            .prepare("SELECT nick, choice FROM poll_votes WHERE channel = ?")?
+            .query_map(&[channel], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)? as usize))
+            })?
+            .collect::<Result<BTreeMap<String, usize>, _>>()?;
+        Ok(Some(Poll {
+            places,
+            votes,
+            deadline: parse_time(&deadline)?,
+            time: parse_time(&time)?,
+            group,
+            created: UNIX_EPOCH + Duration::new(secs as u64, nanos),
+        }))
+    }
+
+    fn load_history(&self, channel: &str) -> Result<Vec<Lunch>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, place, date, time, group_name FROM history WHERE channel = ? ORDER BY id",
+        )?;
+        let rows = statement.query_map(&[channel], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?;
+
+        let mut history = vec![];
+        for row in rows {
+            let (id, place, date, time, group) = row?;
+            let mut attendees = self
+                .connection
+                .prepare("SELECT nick FROM history_attendees WHERE history_id = ? ORDER BY rowid")?;
+            let attendees = attendees
+                .query_map(&[id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            history.push(Lunch {
+                date: NaiveDate::parse_from_str(&date, DATE_FORMAT)?,
+                place,
+                time: parse_time(&time)?,
+                group,
+                attendees,
+            });
+        }
+        Ok(history)
+    }
+}
+
+/// Preferences are shared by all channels, so they are saved with any of them
+fn save_preferences(
+    transaction: &Transaction,
+    preferences: &BTreeMap<String, Preferences>,
+) -> Result<(), Error> {
+    for table in &["vetoes", "diets", "subscriptions"] {
+        transaction.execute(&format!("DELETE FROM {}", table), NO_PARAMS)?;
+    }
+    for (nick, preferences) in preferences {
+        for (position, place) in preferences.vetoes.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO vetoes (nick, position, place) VALUES (?, ?, ?)",
+                &[nick as &dyn ToSql, &(position as u32), place],
+            )?;
+        }
+        for (position, tag) in preferences.diet.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO diets (nick, position, tag) VALUES (?, ?, ?)",
+                &[nick as &dyn ToSql, &(position as u32), tag],
+            )?;
+        }
+        if preferences.subscribed {
+            transaction.execute("INSERT INTO subscriptions (nick) VALUES (?)", &[nick])?;
+        }
+    }
+    Ok(())
+}
+
+fn save_state(transaction: &Transaction, state: &LunchBotState) -> Result<(), Error> {
+    let channel = &state.channel;
+    let tables = [
+        "channels",
+        "groups",
+        "group_members",
+        "group_owners",
+        "proposals",
+        "attendees",
+        "proposal_owners",
+        "places",
+        "place_aliases",
+        "place_tags",
+        "place_owners",
+        "polls",
+        "poll_places",
+        "poll_votes",
+    ];
+    for table in &tables {
+        let column = if *table == "channels" { "name" } else { "channel" };
+        transaction.execute(
+            &format!("DELETE FROM {} WHERE {} = ?", table, column),
+            &[channel],
+        )?;
+    }
+
+    transaction.execute(
+        "INSERT INTO channels (name, store, last_proposal_id) VALUES (?, ?, ?)",
+        &[channel as &dyn ToSql, &state.store, &state.last_proposal_id],
+    )?;
+    for (position, group) in state.groups.iter().enumerate() {
+        let position = position as u32;
+        transaction.execute(
+            "INSERT INTO groups (channel, position, name) VALUES (?, ?, ?)",
+            &[channel as &dyn ToSql, &position, &group.name],
+        )?;
+        for (member_position, nick) in group.users.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO group_members (channel, group_position, position, nick) \
+                 VALUES (?, ?, ?, ?)",
+                &[channel as &dyn ToSql, &position, &(member_position as u32), nick],
+            )?;
+        }
+        if let Some(ref owner) = group.owner {
+            transaction.execute(
+                "INSERT INTO group_owners (channel, group_position, owner) VALUES (?, ?, ?)",
+                &[channel as &dyn ToSql, &position, owner],
+            )?;
+        }
+    }
+
+    for (position, place) in state.places.places().iter().enumerate() {
+        let position = position as u32;
+        transaction.execute(
+            "INSERT INTO places (channel, position, name, address, walking_minutes, notes) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+            &[
+                channel as &dyn ToSql,
+                &position,
+                &place.name,
+                &place.address,
+                &place.walking_minutes,
+                &place.notes,
+            ],
+        )?;
+        for (alias_position, alias) in place.aliases.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO place_aliases (channel, place_position, position, alias) \
+                 VALUES (?, ?, ?, ?)",
+                &[channel as &dyn ToSql, &position, &(alias_position as u32), alias],
+            )?;
+        }
+        for (tag_position, tag) in place.tags.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO place_tags (channel, place_position, position, tag) \
+                 VALUES (?, ?, ?, ?)",
+                &[channel as &dyn ToSql, &position, &(tag_position as u32), tag],
+            )?;
+        }
+        if let Some(ref owner) = place.owner {
+            transaction.execute(
+                "INSERT INTO place_owners (channel, place_position, owner) VALUES (?, ?, ?)",
+                &[channel as &dyn ToSql, &position, owner],
+            )?;
+        }
+    }
+
+    if let Some(ref poll) = state.poll {
+        let created = since_epoch(poll.created);
+        transaction.execute(
+            "INSERT INTO polls (channel, deadline, time, group_name, created_secs, \
+             created_nanos) VALUES (?, ?, ?, ?, ?, ?)",
+            &[
+                channel as &dyn ToSql,
+                &poll.deadline.format(TIME_FORMAT).to_string(),
+                &poll.time.format(TIME_FORMAT).to_string(),
+                &poll.group,
+                &(created.as_secs() as i64),
+                &created.subsec_nanos(),
+            ],
+        )?;
+        for (position, place) in poll.places.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO poll_places (channel, position, place) VALUES (?, ?, ?)",
+                &[channel as &dyn ToSql, &(position as u32), place],
+            )?;
+        }
+        for (nick, &choice) in &poll.votes {
+            transaction.execute(
+                "INSERT INTO poll_votes (channel, nick, choice) VALUES (?, ?, ?)",
+                &[channel as &dyn ToSql, nick, &(choice as u32)],
+            )?;
+        }
+    }
+
+    for proposal in &state.proposals {
+        let created = since_epoch(proposal.created);
+        let (meeting_place, meeting_time) = match proposal.meeting_point {
+            Some((ref place, time)) => (Some(place), Some(time.format(TIME_FORMAT).to_string())),
+            None => (None, None),
+        };
+        transaction.execute(
+            "INSERT INTO proposals (channel, id, place, time, group_name, meeting_place, \
+             meeting_time, created_secs, created_nanos, reminded, meeting_reminded) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &[
+                channel as &dyn ToSql,
+                &proposal.id,
+                &proposal.place,
+                &proposal.time.format(TIME_FORMAT).to_string(),
+                &proposal.group,
+                &meeting_place,
+                &meeting_time,
+                &(created.as_secs() as i64),
+                &created.subsec_nanos(),
+                &proposal.reminded,
+                &proposal.meeting_reminded,
+            ],
+        )?;
+        for (position, nick) in proposal.attendees.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO attendees (channel, proposal_id, position, nick) \
+                 VALUES (?, ?, ?, ?)",
+                &[channel as &dyn ToSql, &proposal.id, &(position as u32), nick],
+            )?;
+        }
+        if let Some(ref owner) = proposal.owner {
+            transaction.execute(
+                "INSERT INTO proposal_owners (channel, proposal_id, owner) VALUES (?, ?, ?)",
+                &[channel as &dyn ToSql, &proposal.id, owner],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+impl Storage for Sqlite {
+    fn load(&mut self, states: &mut ChannelStates) -> Result<(), Error> {
+        let channels = self
+            .connection
+            .prepare("SELECT name, store, last_proposal_id FROM channels")?
+            .query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<(String, u32, u32)>, _>>()?;
+
+        for (channel, store, last_proposal_id) in channels {
+            let mut state = LunchBotState::new(&channel);
+            state.store = store;
+            state.last_proposal_id = last_proposal_id;
+            state.groups = self.load_groups(&channel)?;
+            state.proposals = self.load_proposals(&channel)?;
+            state.history = self.load_history(&channel)?;
+            state.places = self.load_places(&channel)?;
+            state.poll = self.load_poll(&channel)?;
+            states.insert(state);
+        }
+        states.preferences = self.load_preferences()?;
+        Ok(())
+    }
+
+    fn save(&mut self, states: &ChannelStates, channel: &str) -> Result<(), Error> {
+        let state = match states.get(channel) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let transaction = self.connection.transaction()?;
+        save_state(&transaction, state)?;
+        save_preferences(&transaction, &states.preferences)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn archive(&mut self, channel: &str, proposals: &[Proposal]) -> Result<(), Error> {
+        let archived = since_epoch(SystemTime::now()).as_secs() as i64;
+        let transaction = self.connection.transaction()?;
+        for proposal in proposals {
+            // The day the lunch took place, not the one of the proposal
+            let lunch = Lunch::new(proposal);
+            transaction.execute(
+                "INSERT INTO history (channel, proposal_id, place, date, time, group_name, \
+                 created_secs, archived_secs) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                &[
+                    &channel as &dyn ToSql,
+                    &proposal.id,
+                    &lunch.place,
+                    &lunch.date.format(DATE_FORMAT).to_string(),
+                    &lunch.time.format(TIME_FORMAT).to_string(),
+                    &lunch.group,
+                    &(since_epoch(proposal.created).as_secs() as i64),
+                    &archived,
+                ],
+            )?;
+            let history_id = transaction.last_insert_rowid();
+            for nick in &lunch.attendees {
+                transaction.execute(
+                    "INSERT INTO history_attendees (history_id, nick) VALUES (?, ?)",
+                    &[&history_id as &dyn ToSql, nick],
+                )?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn in_memory() -> Sqlite {
+    Sqlite::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+}
+
+#[test]
+fn save_and_load() {
+    let mut states = ChannelStates::new();
+    {
+        let state = states.get_or_insert("#lunch");
+        state.groups.push(Group::new("team", vec!["jan", "ondra"]));
+        state.groups[0].owner = Some("*!~jan@example.com".to_string());
+        state.groups.push(Group::new("others", vec!["pepa"]));
+        let mut proposal = Proposal::new_with_group(
+            "winston",
+            NaiveTime::from_hms(12, 0, 0),
+            "team",
+            Some(("lobby", NaiveTime::from_hms(11, 50, 0))),
+        );
+        proposal.id = 4;
+        proposal.join("pepa");
+        proposal.owner = Some("jan".to_string());
+        state.proposals.push(proposal);
+        state.last_proposal_id = 4;
+        let mut place = Place::new("Winston");
+        place.walking_minutes = Some(5);
+        place.tags = vec!["vegetarian".to_string(), "vegan".to_string()];
+        place.owner = Some("*!~ondra@example.com".to_string());
+        state.places.add(place).unwrap();
+        state.places.add(Place::new("U Kulečníku")).unwrap();
+        state.places.alias("winston", "win".to_string()).unwrap();
+        let mut votes = BTreeMap::new();
+        votes.insert("jan".to_string(), 1);
+        state.poll = Some(Poll {
+            places: vec!["Winston".to_string(), "pho".to_string()],
+            votes,
+            deadline: NaiveTime::from_hms(11, 30, 0),
+            time: NaiveTime::from_hms(11, 45, 0),
+            group: Some("team".to_string()),
+            created: UNIX_EPOCH + Duration::new(1_532_000_000, 42),
+        });
+    }
+    states.get_or_insert("#empty");
+    let mut preferences = Preferences::default();
+    preferences.vetoes.push("U Kulečníku".to_string());
+    preferences.diet.push("vegan".to_string());
+    preferences.subscribed = true;
+    states.preferences.insert("jan".to_string(), preferences);
+    let mut preferences = Preferences::default();
+    preferences.vetoes.push("Winston".to_string());
+    states.preferences.insert("ondra".to_string(), preferences);
+
+    let mut storage = in_memory();
+    storage.save(&states, "#lunch").unwrap();
+    storage.save(&states, "#empty").unwrap();
+    // Saving again replaces the rows of the channel
+    storage.save(&states, "#lunch").unwrap();
+
+    let mut loaded = ChannelStates::new();
+    storage.load(&mut loaded).unwrap();
+    assert_eq!(loaded, states);
+}
+
+#[test]
+fn archive_expired_proposals() {
+    let mut storage = in_memory();
+    let mut states = ChannelStates::new();
+    states.get_or_insert("#lunch");
+    storage.save(&states, "#lunch").unwrap();
+
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(12, 0, 0), None);
+    proposal.join("jan");
+    proposal.join("ondra");
+    storage.archive("#lunch", &[proposal]).unwrap();
+
+    let mut loaded = ChannelStates::new();
+    storage.load(&mut loaded).unwrap();
+    let history = &loaded.get("#lunch").unwrap().history;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].place, "winston");
+    assert_eq!(history[0].attendees, vec!["jan", "ondra"]);
+}
+
+#[test]
+fn archived_lunches_keep_their_date() {
+    use chrono::{Local, TimeZone};
+
+    let mut storage = in_memory();
+    let mut states = ChannelStates::new();
+    states.get_or_insert("#lunch");
+    storage.save(&states, "#lunch").unwrap();
+
+    // Proposed in the evening for the lunch of the next day
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(12, 0, 0), None);
+    proposal.created = Local.ymd(2018, 7, 20).and_hms(18, 0, 0).into();
+    proposal.join("jan");
+    storage.archive("#lunch", &[proposal.clone()]).unwrap();
+
+    let mut loaded = ChannelStates::new();
+    storage.load(&mut loaded).unwrap();
+    let history = &loaded.get("#lunch").unwrap().history;
+    assert_eq!(history, &vec![Lunch::new(&proposal)]);
+    assert_eq!(history[0].date, NaiveDate::from_ymd(2018, 7, 21));
+}
@@ -1,17 +1,51 @@
+use std::collections::btree_map::{self, BTreeMap};
 use std::fmt;
+use std::mem;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
+use chrono::{self, DateTime, Local, NaiveDate, NaiveTime};
+use rand;
+use serde::{Deserialize, Deserializer};
 use serde_json;
 
-use super::syntax::{parse_command, ListOptions};
+use super::help::help;
+use super::migration::migrate;
+use super::permissions::Identity;
+use super::places::Catalogue;
+use super::stats;
+use super::storage::Storage;
+use super::suggest::{suggest, RECENT_DAYS};
+use super::syntax::{
+    did_you_mean, parse_command, ListOptions, LunchCommand, ParseError, PlaceCommand,
+    ProposalEdit,
+};
 
 pub type User = String;
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+/// How long a poll stays open when no deadline is given
+const POLL_MINUTES: i64 = 30;
+
+/// Polls close on the day they were opened, a default deadline after midnight is moved here
+fn default_deadline(now: NaiveTime) -> NaiveTime {
+    match now.overflowing_add_signed(chrono::Duration::minutes(POLL_MINUTES)) {
+        (deadline, 0) => deadline,
+        _ => NaiveTime::from_hms(23, 59, 59),
+    }
+}
+
+/// Nick without suffixes like |wfh or |lunch, which is how users are listed in groups
+pub fn base_nick(nick: &str) -> &str {
+    nick.split('|').next().unwrap_or(nick)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Group {
-    name: String,
-    users: Vec<User>,
+    pub(crate) name: String,
+    pub(crate) users: Vec<User>,
+    /// Pattern matching whoever created the group, see `Identity::pattern`
+    #[serde(default)]
+    pub(crate) owner: Option<String>,
 }
 
 impl Group {
@@ -19,17 +53,35 @@ impl Group {
     where
         T: Into<String>,
     {
-        Group {
+        let mut group = Group {
             name: name.into(),
-            users: users.into_iter().map(Into::into).collect(),
+            users: vec![],
+            owner: None,
+        };
+        for user in users {
+            group.push_user(user);
         }
+        group
     }
 
-    pub fn push_user<T>(&mut self, user: T)
+    /// Returns false when the user already is a member
+    pub fn push_user<T>(&mut self, user: T) -> bool
     where
         T: Into<String>,
     {
-        self.users.push(user.into());
+        let user = user.into();
+        if self.users.contains(&user) {
+            return false;
+        }
+        self.users.push(user);
+        true
+    }
+
+    /// Returns false when the user is not a member
+    pub fn remove_user(&mut self, user: &str) -> bool {
+        let length = self.users.len();
+        self.users.retain(|u| u != user);
+        self.users.len() < length
     }
 
     /// When using IRC, we usually set names with some appendix such as
@@ -47,6 +99,7 @@ impl Group {
                         .map(|u| u.to_string())
                 })
                 .collect(),
+            owner: None,
         }
     }
 }
@@ -57,64 +110,312 @@ impl fmt::Display for Group {
     }
 }
 
-#[derive(Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct Proposal {
-    place: String,
-    time: String,
-    group: Option<String>,
-    meeting_point: Option<(String, String)>,
-    created: SystemTime,
+    #[serde(default)]
+    pub(crate) id: u32,
+    pub(crate) place: String,
+    pub(crate) time: NaiveTime,
+    pub(crate) group: Option<String>,
+    pub(crate) meeting_point: Option<(String, NaiveTime)>,
+    pub(crate) created: SystemTime,
+    #[serde(default)]
+    pub(crate) attendees: Vec<User>,
+    #[serde(default)]
+    pub(crate) reminded: bool,
+    #[serde(default)]
+    pub(crate) meeting_reminded: bool,
+    /// Pattern matching whoever proposed it, see `Identity::pattern`
+    #[serde(default)]
+    pub(crate) owner: Option<String>,
 }
 
 impl fmt::Debug for Proposal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mtg_point = if let Some(ref p) = self.meeting_point {
-            format!(" - meeting {} at {}", p.0, p.1)
+            format!(" - meeting {} at {}", p.0, p.1.format("%H:%M"))
         } else {
             "".to_string()
         };
-        write!(f, "{} at {}{}", self.place, self.time, mtg_point)
+        let attendees = if self.attendees.is_empty() {
+            "".to_string()
+        } else {
+            format!(" ({} going: {})", self.attendees.len(), self.attendees.join(","))
+        };
+        write!(
+            f,
+            "#{} {} at {}{}{}",
+            self.id,
+            self.place,
+            self.time.format("%H:%M"),
+            mtg_point,
+            attendees
+        )
     }
 }
 
 impl Proposal {
-    pub fn new<T>(place: T, time: T, meeting_point: Option<(T, T)>) -> Proposal
+    pub fn new<T>(place: T, time: NaiveTime, meeting_point: Option<(T, NaiveTime)>) -> Proposal
     where
         T: Into<String>,
     {
         Proposal {
+            id: 0,
             place: place.into(),
-            time: time.into(),
+            time,
             group: None,
-            meeting_point: meeting_point.map(|(a,b)| (a.into(), b.into())),
+            meeting_point: meeting_point.map(|(a, b)| (a.into(), b)),
             created: SystemTime::now(),
+            attendees: vec![],
+            reminded: false,
+            meeting_reminded: false,
+            owner: None,
         }
     }
 
-    pub fn new_with_group<T>(place: T, time: T, group: T, meeting_point: Option<(T, T)>) -> Proposal
+    pub fn new_with_group<T>(
+        place: T,
+        time: NaiveTime,
+        group: T,
+        meeting_point: Option<(T, NaiveTime)>,
+    ) -> Proposal
     where
         T: Into<String>,
     {
         Proposal {
+            id: 0,
             place: place.into(),
-            time: time.into(),
+            time,
             group: Some(group.into()),
-            meeting_point: meeting_point.map(|(a,b)| (a.into(), b.into())),
+            meeting_point: meeting_point.map(|(a, b)| (a.into(), b)),
             created: SystemTime::now(),
+            attendees: vec![],
+            reminded: false,
+            meeting_reminded: false,
+            owner: None,
+        }
+    }
+
+    pub fn set_place<T>(&mut self, place: T)
+    where
+        T: Into<String>,
+    {
+        self.place = place.into();
+    }
+
+    /// When `time` comes, on the day of the proposal or on the next day when the time had already
+    /// passed, like in an evening proposal for tomorrow's lunch
+    fn at(&self, time: NaiveTime) -> DateTime<Local> {
+        let created = DateTime::<Local>::from(self.created);
+        let today = created.date().and_time(time).unwrap_or(created);
+        // A time in the minute of the proposal still means today
+        if created < today + chrono::Duration::minutes(1) {
+            return today;
         }
+        created
+            .date()
+            .succ()
+            .and_time(time)
+            .unwrap_or(today + chrono::Duration::days(1))
     }
+
+    pub fn set_time(&mut self, time: NaiveTime) {
+        self.time = time;
+        self.reminded = false;
+    }
+
+    /// Returns false if the user has already joined
+    /// Attendees are kept by their base nick, so that nobody goes twice under two nicks
+    pub fn join<T>(&mut self, user: T) -> bool
+    where
+        T: Into<String>,
+    {
+        let user = base_nick(&user.into()).to_string();
+        if self.attendees.contains(&user) {
+            false
+        } else {
+            self.attendees.push(user);
+            true
+        }
+    }
+
+    /// Returns false if the user was not going anyway
+    pub fn leave(&mut self, user: &str) -> bool {
+        let length = self.attendees.len();
+        self.attendees.retain(|u| u != base_nick(user));
+        self.attendees.len() < length
+    }
+
+    /// Group members and attendees with their current nicks, so that they get highlighted
+    fn people_to_remind(&self, groups: &[Group], users: &[User]) -> Vec<User> {
+        let mut people = self
+            .group
+            .as_ref()
+            .and_then(|name| groups.iter().find(|g| &g.name == name))
+            .map(|g| g.update_names(users.to_vec()).users)
+            .unwrap_or_default();
+        let attendees = Group::new(String::new(), self.attendees.clone());
+        for user in attendees.update_names(users.to_vec()).users {
+            if !people.contains(&user) {
+                people.push(user);
+            }
+        }
+        people
+    }
+
+    fn attendee_list(&self) -> String {
+        if self.attendees.is_empty() {
+            "nobody".to_string()
+        } else {
+            self.attendees.join(",")
+        }
+    }
+}
+
+/// Places to choose from until the deadline, the winner becomes a proposal
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Poll {
+    pub(crate) places: Vec<String>,
+    /// Index of the chosen place by base nick, users can change their mind
+    pub(crate) votes: BTreeMap<User, usize>,
+    pub(crate) deadline: NaiveTime,
+    /// Lunch time of the proposal
+    pub(crate) time: NaiveTime,
+    pub(crate) group: Option<String>,
+    pub(crate) created: SystemTime,
+}
+
+impl Poll {
+    fn closes_at(&self) -> DateTime<Local> {
+        let created = DateTime::<Local>::from(self.created);
+        created.date().and_time(self.deadline).unwrap_or(created)
+    }
+
+    fn counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.places.len()];
+        for &choice in self.votes.values() {
+            if let Some(count) = counts.get_mut(choice) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    /// The first of the places with the most votes, nobody may have voted though
+    fn winner(&self) -> Option<usize> {
+        let counts = self.counts();
+        let most = *counts.iter().max()?;
+        if most == 0 {
+            return None;
+        }
+        counts.iter().position(|&count| count == most)
+    }
+
+    fn standings(&self) -> String {
+        let counts = self.counts();
+        self.places
+            .iter()
+            .zip(counts)
+            .enumerate()
+            .map(|(i, (place, count))| format!("{}) {} ({})", i + 1, place, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Decides when a proposal is old enough to be removed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExpiryPolicy {
+    /// Remove the proposal this long after its lunch time
+    AfterLunch(chrono::Duration),
+    /// Remove the proposal this long after it was created
+    AfterCreation(chrono::Duration),
+}
+
+impl ExpiryPolicy {
+    /// `policy` is either `after-lunch` or `after-creation`
+    pub fn new(policy: &str, minutes: i64) -> Option<ExpiryPolicy> {
+        let interval = chrono::Duration::minutes(minutes);
+        match policy {
+            "after-lunch" => Some(ExpiryPolicy::AfterLunch(interval)),
+            "after-creation" => Some(ExpiryPolicy::AfterCreation(interval)),
+            _ => None,
+        }
+    }
+
+    fn expires_at(&self, proposal: &Proposal) -> DateTime<Local> {
+        let created = DateTime::<Local>::from(proposal.created);
+        match *self {
+            ExpiryPolicy::AfterLunch(interval) => proposal.at(proposal.time) + interval,
+            ExpiryPolicy::AfterCreation(interval) => created + interval,
+        }
+    }
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> ExpiryPolicy {
+        ExpiryPolicy::AfterLunch(chrono::Duration::hours(1))
+    }
+}
+
+/// A proposal that is over, kept for statistics
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Lunch {
+    pub date: NaiveDate,
+    pub place: String,
+    pub time: NaiveTime,
+    pub group: Option<String>,
+    pub attendees: Vec<User>,
+}
+
+impl Lunch {
+    pub fn new(proposal: &Proposal) -> Lunch {
+        Lunch {
+            date: proposal.at(proposal.time).date().naive_local(),
+            place: proposal.place.clone(),
+            time: proposal.time,
+            group: proposal.group.clone(),
+            attendees: proposal.attendees.clone(),
+        }
+    }
+}
+
+/// Personal settings of a user, they can only be changed in a private message
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct Preferences {
+    /// Places that are never suggested
+    pub(crate) vetoes: Vec<String>,
+    /// Tags that every suggested place has to have
+    pub(crate) diet: Vec<String>,
+    /// Reminders are sent privately too
+    #[serde(default)]
+    pub(crate) subscribed: bool,
 }
 
 pub trait StateUpdateCallbacks {
     fn get_list_of_users(&self, channel: &str) -> Vec<User>;
+    /// Today's menu of `place`, None when it has to be downloaded first and is sent later
+    fn menu(&self, place: &str) -> Option<String>;
+    /// Sends a private message
+    fn notify(&self, nick: &str, message: &str);
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct LunchBotState {
-    groups: Vec<Group>,
-    proposals: Vec<Proposal>,
-    store: u32,
-    channel: String,
+    pub(crate) groups: Vec<Group>,
+    pub(crate) proposals: Vec<Proposal>,
+    pub(crate) store: u32,
+    pub(crate) channel: String,
+    /// Last identifier handed out to a proposal, so that identifiers are never reused
+    #[serde(default)]
+    pub(crate) last_proposal_id: u32,
+    /// Expired proposals
+    #[serde(default)]
+    pub(crate) history: Vec<Lunch>,
+    #[serde(default)]
+    pub(crate) places: Catalogue,
+    #[serde(default)]
+    pub(crate) poll: Option<Poll>,
 }
 
 impl LunchBotState {
@@ -124,6 +425,10 @@ impl LunchBotState {
             proposals: vec![],
             store: 0,
             channel: channel.to_owned(),
+            last_proposal_id: 0,
+            history: vec![],
+            places: Catalogue::default(),
+            poll: None,
         }
     }
 
@@ -141,6 +446,216 @@ impl LunchBotState {
         }
     }
 
+    /// Proposals and the poll of the group move to the new name too. Past lunches are archived,
+    /// they keep the name the group had back then.
+    fn rename_group(&mut self, name: &str, new_name: &str) -> bool {
+        match self.get_group(name) {
+            Some(group) => group.name = new_name.to_string(),
+            None => return false,
+        }
+        let rename = |group: &mut Option<String>| {
+            if group.as_ref().map_or(false, |g| g == name) {
+                *group = Some(new_name.to_string());
+            }
+        };
+        for proposal in &mut self.proposals {
+            rename(&mut proposal.group);
+        }
+        if let Some(ref mut poll) = self.poll {
+            rename(&mut poll.group);
+        }
+        true
+    }
+
+    fn unknown_group(&self, name: &str) -> String {
+        match did_you_mean(name, self.groups.iter().map(|g| g.name.as_str())) {
+            Some(similar) => format!("unknown group `{}`, did you mean `{}`?", name, similar),
+            None => format!("unknown group `{}`", name),
+        }
+    }
+
+    fn new_proposal_id(&mut self) -> u32 {
+        self.last_proposal_id += 1;
+        self.last_proposal_id
+    }
+
+    fn get_proposal<'a>(&'a mut self, id: u32) -> Option<&'a mut Proposal> {
+        self.proposals.iter_mut().find(|p| p.id == id)
+    }
+
+    fn remove_proposal(&mut self, id: u32) -> Option<Proposal> {
+        let position = self.proposals.iter().position(|p| p.id == id)?;
+        Some(self.proposals.remove(position))
+    }
+
+    fn propose<T>(
+        &mut self,
+        place: String,
+        time: NaiveTime,
+        group: Option<String>,
+        meeting_point: Option<(String, NaiveTime)>,
+        owner: Option<String>,
+        cb: &T,
+    ) -> String
+    where
+        T: StateUpdateCallbacks,
+    {
+        if let Some(group) = group {
+            // Unfortunately I need to borrow in advance in order to prevent lifetime
+            // collisions.
+            let channel = self.channel.clone();
+            let updated_names = match self.get_group(&group) {
+                Some(g) => g.update_names(cb.get_list_of_users(&channel)),
+                None => return self.unknown_group(&group),
+            };
+            let mut proposal = Proposal::new_with_group(place, time, group, meeting_point);
+            proposal.id = self.new_proposal_id();
+            proposal.owner = owner;
+            info!("Proposal {:?}, names {:?}", proposal, updated_names);
+            let ret = format!("{} go to {:?}", updated_names, &proposal);
+            self.proposals.push(proposal);
+            ret
+        } else {
+            let mut proposal = Proposal::new(place, time, meeting_point);
+            proposal.id = self.new_proposal_id();
+            proposal.owner = owner;
+            let ret = format!("New proposal: go to {:?}", proposal);
+            self.proposals.push(proposal);
+            ret
+        }
+    }
+
+    /// Suggests a place that suits the group, or the user alone
+    fn suggest<T>(
+        &mut self,
+        sender: &Identity,
+        group: Option<String>,
+        time: Option<NaiveTime>,
+        preferences: &BTreeMap<User, Preferences>,
+        recent_days: i64,
+        cb: &T,
+    ) -> String
+    where
+        T: StateUpdateCallbacks,
+    {
+        let people = match group {
+            Some(ref group) => match self.groups.iter().find(|g| &g.name == group) {
+                Some(g) => g.users.clone(),
+                None => return self.unknown_group(group),
+            },
+            None => vec![base_nick(&sender.nick).to_string()],
+        };
+        let preferences: Vec<&Preferences> = people
+            .iter()
+            .filter_map(|user| preferences.get(base_nick(user)))
+            .collect();
+        let vetoes: Vec<&str> = preferences
+            .iter()
+            .flat_map(|p| p.vetoes.iter().map(String::as_str))
+            .collect();
+        let diet: Vec<&str> = preferences
+            .iter()
+            .flat_map(|p| p.diet.iter().map(String::as_str))
+            .collect();
+
+        let today = Local::today().naive_local();
+        let (places, history) = (&self.places, &self.history);
+        let mut rng = rand::thread_rng();
+        let place = suggest(places, history, &vetoes, &diet, today, recent_days, &mut rng);
+        match (place, time) {
+            (None, _) => match group {
+                Some(group) => format!("There is no place that suits everybody in {}", group),
+                None => "There is no place that suits you".to_string(),
+            },
+            (Some(place), None) => format!("How about {}?", place),
+            (Some(place), Some(time)) => {
+                let owner = sender.pattern();
+                let proposal = self.propose(place.clone(), time, group, None, owner, cb);
+                format!("How about {}? {}", place, proposal)
+            }
+        }
+    }
+
+    fn open_poll(
+        &mut self,
+        places: Vec<String>,
+        deadline: NaiveTime,
+        time: NaiveTime,
+        group: Option<String>,
+        now: NaiveTime,
+    ) -> String {
+        if deadline <= now {
+            return format!("The deadline {} has already passed", deadline.format("%H:%M"));
+        }
+        if let Some(ref poll) = self.poll {
+            return format!(
+                "A poll is already open until {}: {}",
+                poll.deadline.format("%H:%M"),
+                poll.standings()
+            );
+        }
+        if let Some(ref group) = group {
+            if !self.groups.iter().any(|g| &g.name == group) {
+                return self.unknown_group(group);
+            }
+        }
+        if time < deadline {
+            return format!(
+                "Lunch at {} would be before the poll closes at {}",
+                time.format("%H:%M"),
+                deadline.format("%H:%M")
+            );
+        }
+
+        let mut unique: Vec<String> = vec![];
+        for place in places {
+            let place = self.places.canonical(&place);
+            if !unique.contains(&place) {
+                unique.push(place);
+            }
+        }
+        let poll = Poll {
+            places: unique,
+            votes: BTreeMap::new(),
+            deadline,
+            time,
+            group,
+            created: SystemTime::now(),
+        };
+        let ret = format!(
+            "Poll until {}: {} - vote with `lb vote <number>`",
+            deadline.format("%H:%M"),
+            poll.standings()
+        );
+        self.poll = Some(poll);
+        ret
+    }
+
+    /// Closes the poll once its deadline passes and proposes the winner
+    pub fn close_poll<T>(&mut self, now: DateTime<Local>, cb: &T) -> Option<String>
+    where
+        T: StateUpdateCallbacks,
+    {
+        if now < self.poll.as_ref()?.closes_at() {
+            return None;
+        }
+        let poll = self.poll.take()?;
+        match poll.winner() {
+            Some(winner) => {
+                let place = poll.places[winner].clone();
+                let (time, group) = (poll.time, poll.group.clone());
+                let proposal = self.propose(place.clone(), time, group, None, None, cb);
+                Some(format!(
+                    "The poll is closed, {} won: {}\r\n{}",
+                    place,
+                    poll.standings(),
+                    proposal
+                ))
+            }
+            None => Some(format!("The poll is closed, nobody voted: {}", poll.standings())),
+        }
+    }
+
     pub fn list_of_groups(&self) -> String {
         self.groups
             .iter()
@@ -149,118 +664,1259 @@ impl LunchBotState {
             .join(",")
     }
 
-    pub fn remove_old_proposals(&mut self) {
-        let dur = Duration::from_secs(60 * 60 * 2);
-        self.proposals.retain(|p| {
-            if let Ok(d) = p.created.elapsed() {
-                d < dur
-            } else {
-                true
-            }
-        });
+    /// Moves expired proposals to the history, they are returned so that they can be archived
+    pub fn remove_old_proposals(
+        &mut self,
+        policy: ExpiryPolicy,
+        now: DateTime<Local>,
+    ) -> Vec<Proposal> {
+        let (current, expired): (Vec<_>, Vec<_>) = self
+            .proposals
+            .drain(..)
+            .partition(|p| now < policy.expires_at(p));
+        self.proposals = current;
+        self.history.extend(expired.iter().map(Lunch::new));
+        expired
     }
 
+    #[cfg(test)]
     pub fn num_of_proposals(&self) -> usize {
         self.proposals.len()
     }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Collects reminders for proposals whose lunch or meeting time is less than `ahead` away.
+    /// Each of them is reminded only once.
+    /// People who subscribed in their `preferences` get them in a private message too.
+    pub fn take_reminders<T>(
+        &mut self,
+        now: DateTime<Local>,
+        ahead: chrono::Duration,
+        preferences: &BTreeMap<User, Preferences>,
+        cb: &T,
+    ) -> Vec<String>
+    where
+        T: StateUpdateCallbacks,
+    {
+        let is_due = |at: DateTime<Local>| at - ahead <= now && now < at;
+        let groups = &self.groups;
+        let mut users = None;
+        let mut reminders = vec![];
+        for p in self.proposals.iter_mut() {
+            let lunch_due = !p.reminded && is_due(p.at(p.time));
+            let meeting_due = !p.meeting_reminded
+                && p.meeting_point.as_ref().map_or(false, |&(_, time)| is_due(p.at(time)));
+            if !lunch_due && !meeting_due {
+                continue;
+            }
+
+            let channel = &self.channel;
+            let users = users.get_or_insert_with(|| cb.get_list_of_users(channel));
+            let people = p.people_to_remind(groups, users);
+            let first = reminders.len();
+            if meeting_due {
+                p.meeting_reminded = true;
+                if let Some((ref place, time)) = p.meeting_point {
+                    reminders.push(format!(
+                        "{}: meeting {} at {} for lunch at {}",
+                        people.join(","),
+                        place,
+                        time.format("%H:%M"),
+                        p.place
+                    ));
+                }
+            }
+            if lunch_due {
+                p.reminded = true;
+                reminders.push(format!("{}: lunch time! {:?}", people.join(","), p));
+            }
+
+            let subscribed = people.iter().filter(|nick| {
+                preferences
+                    .get(base_nick(nick))
+                    .map_or(false, |p| p.subscribed)
+            });
+            for nick in subscribed {
+                for reminder in &reminders[first..] {
+                    cb.notify(nick, reminder);
+                }
+            }
+        }
+        reminders
+    }
+}
+
+/// Refuses the command unless the sender matches the owner, things without an owner are only
+/// for admins. Unknown things are reported by the command itself.
+fn unless_owner(
+    owner: Option<&Option<String>>,
+    sender: &Identity,
+    refusal: String,
+) -> Option<String> {
+    match owner {
+        Some(&Some(ref owner)) if sender.matches(owner) => None,
+        Some(_) => Some(refusal),
+        None => None,
+    }
+}
+
+/// Every channel the bot is in has its own groups and proposals
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct ChannelStates {
+    /// By the lowercase channel name, see `channel_key`
+    #[serde(deserialize_with = "deserialize_channels")]
+    channels: BTreeMap<String, LunchBotState>,
+    /// Preferences are set in private messages, so they are shared by all channels. By base nick.
+    #[serde(default)]
+    pub(crate) preferences: BTreeMap<User, Preferences>,
+    /// Accounts and hostmasks of admins, they come from the configuration and are never saved
+    #[serde(skip)]
+    admins: Vec<String>,
+    /// Days after which a place is suggested again, from the configuration too
+    #[serde(skip, default = "default_recent_days")]
+    recent_days: i64,
+    /// Command prefix shown in the help
+    #[serde(skip, default = "default_prefix")]
+    prefix: String,
+}
+
+fn default_recent_days() -> i64 {
+    RECENT_DAYS
+}
+
+fn default_prefix() -> String {
+    "lb".to_string()
+}
+
+/// Channel names are case insensitive, `#Lunch` and `#lunch` share the state
+fn channel_key(channel: &str) -> String {
+    channel.to_lowercase()
+}
+
+/// Older backups could have kept the channels as they were written
+fn deserialize_channels<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, LunchBotState>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let channels: BTreeMap<String, LunchBotState> = BTreeMap::deserialize(deserializer)?;
+    Ok(channels
+        .into_iter()
+        .map(|(channel, state)| (channel_key(&channel), state))
+        .collect())
+}
+
+impl ChannelStates {
+    pub fn new() -> Self {
+        ChannelStates {
+            channels: BTreeMap::new(),
+            preferences: BTreeMap::new(),
+            admins: vec![],
+            recent_days: default_recent_days(),
+            prefix: default_prefix(),
+        }
+    }
+
+    pub fn set_admins(&mut self, admins: Vec<String>) {
+        self.admins = admins;
+    }
+
+    pub fn set_recent_days(&mut self, days: i64) {
+        self.recent_days = days;
+    }
+
+    pub fn set_prefix(&mut self, prefix: String) {
+        self.prefix = prefix;
+    }
+
+    pub fn is_admin(&self, sender: &Identity) -> bool {
+        self.admins.iter().any(|admin| sender.matches(admin))
+    }
+
+    /// Commands that cannot be undone are only for admins, groups, proposals and places can be
+    /// changed by their owners too. Returns why the command is refused.
+    fn refusal(&self, cmd: &LunchCommand, channel: &str, sender: &Identity) -> Option<String> {
+        if self.is_admin(sender) {
+            return None;
+        }
+        let state = self.get(channel);
+        let group_owner = |name: &str| {
+            state
+                .and_then(|state| state.groups.iter().find(|g| g.name == name))
+                .map(|group| &group.owner)
+        };
+        let proposal_owner = |id: u32| {
+            state
+                .and_then(|state| state.proposals.iter().find(|p| p.id == id))
+                .map(|proposal| &proposal.owner)
+        };
+        match *cmd {
+            LunchCommand::RestoreState(_) => Some("Only admins can restore the state".to_string()),
+            LunchCommand::DumpState => Some("Only admins can dump the state".to_string()),
+            LunchCommand::GroupRemove(ref name) => unless_owner(
+                group_owner(name),
+                sender,
+                format!("Only the owner of group {} or an admin can remove it", name),
+            ),
+            LunchCommand::GroupRename(ref name, _) => unless_owner(
+                group_owner(name),
+                sender,
+                format!("Only the owner of group {} or an admin can rename it", name),
+            ),
+            // Anybody can leave a group
+            LunchCommand::RemoveUser(ref user, ref name)
+                if base_nick(user) != base_nick(&sender.nick) =>
+            {
+                unless_owner(
+                    group_owner(name),
+                    sender,
+                    format!("Only the owner of group {} or an admin can remove others", name),
+                )
+            }
+            LunchCommand::Edit(id, _) => unless_owner(
+                proposal_owner(id),
+                sender,
+                format!("Only whoever proposed #{} or an admin can edit it", id),
+            ),
+            LunchCommand::Cancel(id) => unless_owner(
+                proposal_owner(id),
+                sender,
+                format!("Only whoever proposed #{} or an admin can cancel it", id),
+            ),
+            LunchCommand::Place(PlaceCommand::Remove(ref name)) => unless_owner(
+                state
+                    .and_then(|state| state.places.find(name))
+                    .map(|place| &place.owner),
+                sender,
+                format!("Only whoever added {} or an admin can remove it", name),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn get_or_insert(&mut self, channel: &str) -> &mut LunchBotState {
+        self.channels
+            .entry(channel_key(channel))
+            .or_insert_with(|| LunchBotState::new(channel))
+    }
+
+    pub fn get(&self, channel: &str) -> Option<&LunchBotState> {
+        self.channels.get(&channel_key(channel))
+    }
+
+    pub fn insert(&mut self, state: LunchBotState) {
+        self.channels.insert(channel_key(&state.channel), state);
+    }
+
+    /// States of all channels together with the preferences they read
+    pub fn states_and_preferences_mut<'a>(
+        &'a mut self,
+    ) -> (btree_map::ValuesMut<'a, String, LunchBotState>, &'a BTreeMap<User, Preferences>) {
+        (self.channels.values_mut(), &self.preferences)
+    }
 }
 
-pub fn update_state<T>(line: &str, state: Arc<Mutex<LunchBotState>>, cb: &T) -> String
+/// Private messages can start with the channel they are meant for, like `#lunch list`. Without
+/// it they go to the only channel, in more channels only personal settings and help work that
+/// way. Returns the channel and the rest of the `line`, or why the message is refused.
+pub fn private_channel<'a>(
+    line: &'a str,
+    channels: &'a [String],
+) -> Result<(&'a str, &'a str), String> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with('&') {
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim_start()),
+            None => (line, ""),
+        };
+        return match channels.iter().find(|c| c.to_lowercase() == name.to_lowercase()) {
+            Some(channel) => Ok((channel, rest)),
+            None => Err(format!("I am not in {}", name)),
+        };
+    }
+    if channels.len() > 1 {
+        match parse_command(line) {
+            Ok(LunchCommand::Help(_)) | Err(_) => (),
+            Ok(ref cmd) if cmd.is_private() => (),
+            Ok(_) => {
+                return Err(format!(
+                    "I am in {}, say which channel the command is for, like `{} {}`",
+                    channels.join(", "),
+                    channels[0],
+                    line
+                ))
+            }
+        }
+    }
+    Ok((&channels[0], line))
+}
+
+/// Commands are applied to the state of `channel`, which is saved to `storage` if it changed.
+/// Commands sent in a `private` message can change personal settings of the sender. The `line`
+/// no longer contains the prefix or nick the bot was addressed with.
+pub fn update_state<T, S>(
+    line: &str,
+    channel: &str,
+    sender: &Identity,
+    private: bool,
+    states: Arc<Mutex<ChannelStates>>,
+    storage: &mut S,
+    cb: &T,
+) -> String
 where
     T: StateUpdateCallbacks,
+    S: Storage + ?Sized,
 {
     use LunchCommand::*;
 
     let cmd = parse_command(line);
-    if let Some(ref cmd) = cmd {
-        info!("Incoming command: {:?}", cmd);
+    if let Ok(ref cmd) = cmd {
+        info!("Incoming command in {}: {:?}", channel, cmd);
+    }
+    let changes_state = match cmd {
+        // Only showing the open poll, with places it opens one
+        Ok(Poll(ref places, None, None, None)) => !places.is_empty(),
+        Ok(List(_))
+        | Ok(DumpState)
+        | Ok(GroupShow(_))
+        | Ok(Place(PlaceCommand::Info(_)))
+        | Ok(Menu(_))
+        | Ok(Suggest(_, None))
+        | Ok(Stats(_))
+        | Ok(Help(_))
+        | Err(_) => false,
+        _ => true,
+    };
+    if let Ok(ref cmd) = cmd {
+        if cmd.is_private() && !private {
+            return "This command only works in a private message to me".to_string();
+        }
+    }
+    let now = Local::now().time();
+    let mut states = states.lock().unwrap();
+    if let Ok(ref cmd) = cmd {
+        if let Some(refusal) = states.refusal(cmd, channel, sender) {
+            info!("Refused {:?} from {:?}", cmd, sender);
+            return refusal;
+        }
     }
+    // Commands that failed, like joining an unknown proposal, leave the state as it was
+    let before = if changes_state {
+        let state = states.get(channel).cloned();
+        Some((state.unwrap_or_else(|| LunchBotState::new(channel)), states.preferences.clone()))
+    } else {
+        None
+    };
+    let response = run_command(cmd, channel, sender, &mut states, now, cb);
+    let changed = before.map_or(false, |(state, preferences)| {
+        states.get(channel) != Some(&state) || states.preferences != preferences
+    });
+    if changed {
+        if let Err(e) = storage.save(&states, channel) {
+            error!("Failed to save the state of {}: {}", channel, e);
+        }
+    }
+    response
+}
+
+/// Migrates a dump of the channel, or a whole backup, to the current format like the storage
+/// does when loading it
+fn parse_dump(dump: &str, channel: &str) -> Option<(LunchBotState, BTreeMap<User, Preferences>)> {
+    let dump = migrate(serde_json::from_str(dump).ok()?).ok()?;
+    let mut states = serde_json::from_value::<ChannelStates>(dump).ok()?;
+    let state = if states.channels.len() == 1 {
+        states.channels.into_iter().next().map(|(_, state)| state)?
+    } else {
+        states.channels.remove(&channel_key(channel))?
+    };
+    Some((state, states.preferences))
+}
+
+fn run_command<T>(
+    cmd: Result<LunchCommand, ParseError>,
+    channel: &str,
+    identity: &Identity,
+    states: &mut ChannelStates,
+    now: NaiveTime,
+    cb: &T,
+) -> String
+where
+    T: StateUpdateCallbacks,
+{
+    use LunchCommand::*;
+
+    let sender = identity.nick.as_str();
+    let preferences = &mut states.preferences;
+    let recent_days = states.recent_days;
+    let prefix = &states.prefix;
+    let state = states
+        .channels
+        .entry(channel_key(channel))
+        .or_insert_with(|| LunchBotState::new(channel));
     match cmd {
-        Some(Add(n)) => {
-            let store = &mut state.lock().unwrap().store;
-            *store += n;
-            format!("Store: {}", *store)
-        }
-        Some(AddUser(user, group)) => {
-            let state = &mut state.lock().unwrap();
-            if let Some(g) = state.get_group(group) {
-                g.push_user(user);
-                format!("Group {} updated: {}", g.name, g)
+        Ok(Add(n)) => {
+            state.store += n;
+            format!("Store: {}", state.store)
+        }
+        Ok(AddUser(user, group)) => {
+            if let Some(g) = state.get_group(&group) {
+                if g.push_user(user.as_str()) {
+                    format!("Group {} updated: {}", g.name, g)
+                } else {
+                    format!("{} already is in group {}", user, g.name)
+                }
             } else {
-                format!("No group named {}", group)
+                state.unknown_group(&group)
             }
         }
-        Some(GroupAdd(name, users)) => {
-            let group = Group::new(name, users);
-            let ret = format!("New group: {} - {}", name, group);
-            {
-                let groups = &mut state.lock().unwrap().groups;
-                groups.push(group);
+        Ok(RemoveUser(user, group)) => {
+            if let Some(g) = state.get_group(&group) {
+                if g.remove_user(&user) {
+                    format!("Group {} updated: {}", g.name, g)
+                } else {
+                    format!("{} is not in group {}", user, g.name)
+                }
+            } else {
+                state.unknown_group(&group)
+            }
+        }
+        Ok(JoinGroup(group)) => {
+            let user = base_nick(sender);
+            if let Some(g) = state.get_group(&group) {
+                if g.push_user(user) {
+                    format!("{} joined group {}: {}", user, g.name, g)
+                } else {
+                    format!("{} already is in group {}", user, g.name)
+                }
+            } else {
+                state.unknown_group(&group)
             }
+        }
+        Ok(LeaveGroup(group)) => {
+            let user = base_nick(sender);
+            if let Some(g) = state.get_group(&group) {
+                if g.remove_user(user) {
+                    format!("{} left group {}: {}", user, g.name, g)
+                } else {
+                    format!("{} is not in group {}", user, g.name)
+                }
+            } else {
+                state.unknown_group(&group)
+            }
+        }
+        Ok(GroupAdd(name, users)) => {
+            if state.groups.iter().any(|g| g.name == name) {
+                return format!(
+                    "Group {} already exists, add people with `lb add <user> to {}`",
+                    name, name
+                );
+            }
+            let mut group = Group::new(name, users);
+            group.owner = identity.pattern();
+            let ret = format!("New group: {} - {}", group.name, group);
+            state.groups.push(group);
             ret
         }
-        Some(GroupRemove(name)) => {
-            let state = &mut state.lock().unwrap();
-            if state.remove_group(name) {
+        Ok(GroupRemove(name)) => {
+            if state.remove_group(&name) {
                 format!("Group {} has been removed", name)
             } else {
-                format!("No such group: {}", name)
-            }
-        }
-        Some(Propose(place, time, group, meeting_point)) => {
-            if let Some(group) = group {
-                let proposal = Proposal::new_with_group(place, time, group, meeting_point);
-                let ret;
-                {
-                    let state = &mut state.lock().unwrap();
-                    // Unfortunately I need to borrow in advance in order to prevent lifetime
-                    // collisions.
-                    let channel = state.channel.clone();
-                    if let Some(g) = state.get_group(group) {
-                        let users = cb.get_list_of_users(&channel);
-                        let updated_names = g.update_names(users);
-                        info!(
-                            "Proposal {:?}, group {:?}, names {:?}",
-                            proposal, g, updated_names
-                        );
-                        ret = format!("{} go to {:?}", updated_names, &proposal);
-                    } else {
-                        ret = format!("-No such group- go to {:?}", &proposal);
-                    }
-                    state.proposals.push(proposal);
-                }
-                ret
+                state.unknown_group(&name)
+            }
+        }
+        Ok(GroupRename(name, new_name)) => {
+            if state.groups.iter().any(|g| g.name == new_name) {
+                format!("Group {} already exists", new_name)
+            } else if state.rename_group(&name, &new_name) {
+                format!("Group {} is now called {}", name, new_name)
             } else {
-                let proposal = Proposal::new(place, time, meeting_point);
-                let ret = format!("New proposal: go to {:?}", proposal);
-                let proposals = &mut state.lock().unwrap().proposals;
-                proposals.push(proposal);
-                ret
+                state.unknown_group(&name)
             }
         }
-        Some(List(opt)) => match opt {
+        Ok(GroupShow(name)) => match state.groups.iter().find(|g| g.name == name) {
+            Some(g) => format!("Group {}: {}", g.name, g),
+            None => state.unknown_group(&name),
+        },
+        Ok(Propose(place, time, group, meeting_point)) => {
+            let place = state.places.canonical(&place);
+            let time = time.resolve(now);
+            let meeting_point = meeting_point.map(|(place, time)| (place, time.resolve(now)));
+            state.propose(place, time, group, meeting_point, identity.pattern(), cb)
+        }
+        Ok(List(opt)) => match opt {
             ListOptions::Proposals => {
-                let proposals = &state.lock().unwrap().proposals;
+                let mut proposals = state.proposals.iter().collect::<Vec<_>>();
+                proposals.sort_by_key(|p| p.time);
                 format!("All proposals: {:?}", proposals)
             }
-            ListOptions::Groups => {
-                let groups = state.lock().unwrap().list_of_groups();
-                format!("Groups: {}", groups)
+            ListOptions::Groups => format!("Groups: {}", state.list_of_groups()),
+            ListOptions::Places => {
+                let places = state.places.places();
+                let names: Vec<&str> = places.iter().map(|p| p.name.as_str()).collect();
+                format!("Places: {}", names.join(", "))
             }
         },
-        Some(DumpState) => {
-            let state: &LunchBotState = &state.lock().unwrap();
+        Ok(DumpState) => {
             serde_json::to_string(state).unwrap_or("failed to dump state".to_string())
         }
-        Some(RestoreState(input_state_string)) => {
-            if let Ok(new_state) = serde_json::from_str(input_state_string) {
-                let state: &mut LunchBotState = &mut state.lock().unwrap();
+        Ok(RestoreState(input_state_string)) => match parse_dump(&input_state_string, channel) {
+            Some((mut new_state, restored_preferences)) => {
+                // Past lunches are archived by the storage, they are not part of the restore
+                new_state.history = mem::replace(&mut state.history, vec![]);
                 *state = new_state;
+                state.channel = channel.to_string();
+                // Preferences are shared with other channels, newer ones are kept
+                for (nick, restored) in restored_preferences {
+                    preferences.entry(nick).or_insert(restored);
+                }
                 format!("Success")
+            }
+            None => format!("Fail"),
+        },
+        Ok(Join(id)) => {
+            if let Some(p) = state.get_proposal(id) {
+                if p.join(sender) {
+                    format!("{} joined {}, going: {}", sender, p.place, p.attendee_list())
+                } else {
+                    format!("{} is already going to {}", sender, p.place)
+                }
             } else {
-                format!("Fail")
+                format!("No such proposal: #{}", id)
             }
         }
-        _ => include_str!("../usage").to_string(),
+        Ok(Leave(id)) => {
+            if let Some(p) = state.get_proposal(id) {
+                if p.leave(sender) {
+                    format!("{} left {}, going: {}", sender, p.place, p.attendee_list())
+                } else {
+                    format!("{} is not going to {}", sender, p.place)
+                }
+            } else {
+                format!("No such proposal: #{}", id)
+            }
+        }
+        Ok(Cancel(id)) => {
+            if let Some(p) = state.remove_proposal(id) {
+                format!("Proposal {:?} has been cancelled", p)
+            } else {
+                format!("No such proposal: #{}", id)
+            }
+        }
+        Ok(Edit(id, edit)) => {
+            let edit = match edit {
+                ProposalEdit::Place(place) => ProposalEdit::Place(state.places.canonical(&place)),
+                edit => edit,
+            };
+            if let Some(p) = state.get_proposal(id) {
+                match edit {
+                    ProposalEdit::Place(place) => p.set_place(place),
+                    ProposalEdit::Time(time) => p.set_time(time.resolve(now)),
+                }
+                format!("Proposal updated: {:?}", p)
+            } else {
+                format!("No such proposal: #{}", id)
+            }
+        }
+        Ok(Place(command)) => match command {
+            PlaceCommand::Add(mut place) => {
+                place.owner = identity.pattern();
+                match state.places.add(place) {
+                    Ok(place) => format!("New place: {}", place),
+                    Err(e) => e,
+                }
+            }
+            PlaceCommand::Alias(name, alias) => match state.places.alias(&name, alias) {
+                Ok(place) => format!("Place updated: {}", place),
+                Err(e) => e,
+            },
+            PlaceCommand::Tag(name, tags) => match state.places.tag(&name, tags) {
+                Ok(place) => format!("Place updated: {}", place),
+                Err(e) => e,
+            },
+            PlaceCommand::Remove(name) => match state.places.remove(&name) {
+                Ok(place) => format!("Place {} has been removed", place.name),
+                Err(e) => e,
+            },
+            PlaceCommand::Info(name) => state.places.info(&name),
+        },
+        Ok(Menu(place)) => cb.menu(&state.places.canonical(&place)).unwrap_or_default(),
+        Ok(Suggest(group, time)) => {
+            let time = time.map(|time| time.resolve(now));
+            state.suggest(identity, group, time, preferences, recent_days, cb)
+        }
+        Ok(Poll(places, deadline, time, group)) => {
+            if !places.is_empty() {
+                let deadline = deadline.map_or(default_deadline(now), |d| d.resolve(now));
+                let time = time.map_or(deadline, |t| t.resolve(now));
+                return state.open_poll(places, deadline, time, group, now);
+            }
+            match state.poll {
+                Some(ref poll) => format!(
+                    "Poll until {}: {}",
+                    poll.deadline.format("%H:%M"),
+                    poll.standings()
+                ),
+                None => "There is no open poll".to_string(),
+            }
+        }
+        Ok(Vote(option)) => match state.poll {
+            Some(ref mut poll) => {
+                let choice = option as usize;
+                if choice == 0 || choice > poll.places.len() {
+                    return format!("There is no option {} in the poll", option);
+                }
+                let user = base_nick(sender);
+                poll.votes.insert(user.to_string(), choice - 1);
+                format!(
+                    "{} voted for {}: {}",
+                    user,
+                    poll.places[choice - 1],
+                    poll.standings()
+                )
+            }
+            None => "There is no open poll".to_string(),
+        },
+        Ok(Veto(place)) => {
+            let place = state.places.canonical(&place);
+            let user = base_nick(sender);
+            let vetoes = &mut preferences.entry(user.to_string()).or_default().vetoes;
+            if !vetoes.contains(&place) {
+                vetoes.push(place.clone());
+            }
+            format!("{} will not be suggested to {}", place, user)
+        }
+        Ok(Unveto(place)) => {
+            let place = state.places.canonical(&place);
+            let user = base_nick(sender);
+            let vetoes = &mut preferences.entry(user.to_string()).or_default().vetoes;
+            let length = vetoes.len();
+            vetoes.retain(|v| v != &place);
+            if vetoes.len() < length {
+                format!("{} can be suggested to {} again", place, user)
+            } else {
+                format!("{} has not vetoed {}", user, place)
+            }
+        }
+        Ok(Diet(tags)) => {
+            let user = base_nick(sender);
+            let response = if tags.is_empty() {
+                format!("{} eats anywhere", user)
+            } else {
+                format!("{} eats only at places tagged {}", user, tags.join(", "))
+            };
+            preferences.entry(user.to_string()).or_default().diet = tags;
+            response
+        }
+        Ok(Subscribe) => {
+            let user = base_nick(sender);
+            preferences.entry(user.to_string()).or_default().subscribed = true;
+            format!("{} will get reminders in a private message too", user)
+        }
+        Ok(Unsubscribe) => {
+            let user = base_nick(sender);
+            preferences.entry(user.to_string()).or_default().subscribed = false;
+            format!("{} will get reminders only in the channel", user)
+        }
+        Ok(Stats(option)) => {
+            // Lunches from before a place was added to the catalogue count as that place too
+            let places = &state.places;
+            let history: Vec<Lunch> = state
+                .history
+                .iter()
+                .map(|lunch| Lunch {
+                    place: places.canonical(&lunch.place),
+                    ..lunch.clone()
+                })
+                .collect();
+            stats::stats(&history, option, Local::today().naive_local())
+        }
+        Ok(Help(topic)) => help(topic.as_ref().map(String::as_str), prefix),
+        Err(ParseError::MissingCommand) => help(None, prefix),
+        Err(e) => format!("{}", e),
+    }
+}
+
+#[test]
+fn join_and_leave_proposal() {
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(12, 0, 0), None);
+    proposal.id = 3;
+    assert!(proposal.join("jan"));
+    assert!(!proposal.join("jan|wfh"));
+    assert!(proposal.join("ondra|afk"));
+    assert_eq!(format!("{:?}", proposal), "#3 winston at 12:00 (2 going: jan,ondra)");
+    assert!(proposal.leave("jan|wfh"));
+    assert!(!proposal.leave("jan"));
+    assert_eq!(proposal.attendee_list(), "ondra");
+}
+
+#[cfg(test)]
+use super::time::LunchTime;
+
+#[cfg(test)]
+struct UsersOnline(Vec<&'static str>);
+
+/// Remembers which channels were saved
+#[cfg(test)]
+#[derive(Default)]
+struct SavedChannels(Vec<String>);
+
+#[cfg(test)]
+impl Storage for SavedChannels {
+    fn load(&mut self, _states: &mut ChannelStates) -> Result<(), ::failure::Error> {
+        Ok(())
     }
+
+    fn save(&mut self, _states: &ChannelStates, channel: &str) -> Result<(), ::failure::Error> {
+        self.0.push(channel.to_string());
+        Ok(())
+    }
+
+    fn archive(&mut self, _channel: &str, _proposals: &[Proposal]) -> Result<(), ::failure::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl StateUpdateCallbacks for UsersOnline {
+    fn get_list_of_users(&self, _channel: &str) -> Vec<User> {
+        self.0.iter().map(|u| u.to_string()).collect()
+    }
+
+    fn menu(&self, place: &str) -> Option<String> {
+        Some(format!("Menu of {} today:\r\n- soup", place))
+    }
+
+    fn notify(&self, _nick: &str, _message: &str) {}
+}
+
+#[test]
+fn reminders_are_sent_once() {
+    use chrono::TimeZone;
+
+    let day = Local.ymd(2018, 7, 20);
+    let mut state = LunchBotState::new("#test");
+    state.groups.push(Group::new("team", vec!["jan", "ondra"]));
+    let mut proposal = Proposal::new_with_group(
+        "winston",
+        NaiveTime::from_hms(12, 0, 0),
+        "team",
+        Some(("lobby", NaiveTime::from_hms(11, 50, 0))),
+    );
+    proposal.id = 1;
+    proposal.join("pepa");
+    proposal.created = day.and_hms(9, 0, 0).into();
+    state.proposals.push(proposal);
+    let users = UsersOnline(vec!["jan|wfh", "ondra", "pepa", "karel"]);
+    let ahead = chrono::Duration::minutes(10);
+    let preferences = BTreeMap::new();
+    let mut remind = |hour, minute| {
+        state.take_reminders(day.and_hms(hour, minute, 0), ahead, &preferences, &users)
+    };
+
+    assert!(remind(11, 30).is_empty());
+    assert_eq!(
+        remind(11, 45),
+        vec!["jan|wfh,ondra,pepa: meeting lobby at 11:50 for lunch at winston"]
+    );
+    assert_eq!(
+        remind(11, 51),
+        vec!["jan|wfh,ondra,pepa: lunch time! #1 winston at 12:00 - meeting lobby at 11:50 (1 going: pepa)"]
+    );
+    assert!(remind(11, 55).is_empty());
+}
+
+#[test]
+fn proposals_expire_after_lunch() {
+    use chrono::TimeZone;
+
+    let created = Local.ymd(2018, 7, 20).and_hms(9, 0, 0);
+    let mut state = LunchBotState::new("#test");
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(13, 0, 0), None);
+    proposal.created = created.into();
+    state.proposals.push(proposal);
+
+    let policy = ExpiryPolicy::new("after-lunch", 30).unwrap();
+    state.remove_old_proposals(policy, Local.ymd(2018, 7, 20).and_hms(13, 29, 0));
+    assert_eq!(state.num_of_proposals(), 1);
+    state.remove_old_proposals(policy, Local.ymd(2018, 7, 20).and_hms(13, 30, 0));
+    assert_eq!(state.num_of_proposals(), 0);
+    assert_eq!(state.history[0].date, NaiveDate::from_ymd(2018, 7, 20));
+    assert_eq!(state.history[0].place, "winston");
+}
+
+#[test]
+fn proposals_expire_after_creation() {
+    use chrono::TimeZone;
+
+    let created = Local.ymd(2018, 7, 20).and_hms(9, 0, 0);
+    let mut state = LunchBotState::new("#test");
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(13, 0, 0), None);
+    proposal.created = created.into();
+    state.proposals.push(proposal);
+
+    let policy = ExpiryPolicy::new("after-creation", 120).unwrap();
+    state.remove_old_proposals(policy, Local.ymd(2018, 7, 20).and_hms(10, 59, 0));
+    assert_eq!(state.num_of_proposals(), 1);
+    state.remove_old_proposals(policy, Local.ymd(2018, 7, 20).and_hms(11, 0, 0));
+    assert_eq!(state.num_of_proposals(), 0);
+}
+
+#[test]
+fn evening_proposals_are_for_tomorrow() {
+    use chrono::TimeZone;
+
+    let mut state = LunchBotState::new("#test");
+    let mut proposal = Proposal::new("winston", NaiveTime::from_hms(12, 0, 0), None);
+    proposal.id = 1;
+    proposal.created = Local.ymd(2018, 7, 20).and_hms(18, 0, 0).into();
+    state.proposals.push(proposal);
+    let tomorrow = Local.ymd(2018, 7, 21);
+    let users = UsersOnline(vec![]);
+    let ahead = chrono::Duration::minutes(10);
+    let preferences = BTreeMap::new();
+
+    let policy = ExpiryPolicy::new("after-lunch", 60).unwrap();
+    state.remove_old_proposals(policy, Local.ymd(2018, 7, 20).and_hms(18, 1, 0));
+    assert_eq!(state.num_of_proposals(), 1);
+    let reminders = state.take_reminders(tomorrow.and_hms(11, 55, 0), ahead, &preferences, &users);
+    assert_eq!(reminders, vec![": lunch time! #1 winston at 12:00"]);
+    state.remove_old_proposals(policy, tomorrow.and_hms(12, 59, 0));
+    assert_eq!(state.num_of_proposals(), 1);
+    state.remove_old_proposals(policy, tomorrow.and_hms(13, 0, 0));
+    assert_eq!(state.num_of_proposals(), 0);
+    assert_eq!(state.history[0].date, NaiveDate::from_ymd(2018, 7, 21));
+}
+
+#[test]
+fn proposals_shortly_before_midnight_are_for_the_next_day() {
+    use chrono::TimeZone;
+
+    let now = Local.ymd(2018, 7, 20).and_hms(23, 50, 0);
+    let mut state = LunchBotState::new("#test");
+    let time = LunchTime::InMinutes(20).resolve(now.time());
+    let mut proposal = Proposal::new("kebab", time, None);
+    proposal.id = 1;
+    proposal.created = now.into();
+    state.proposals.push(proposal);
+    let tomorrow = Local.ymd(2018, 7, 21);
+    let users = UsersOnline(vec![]);
+    let ahead = chrono::Duration::minutes(10);
+    let preferences = BTreeMap::new();
+
+    let policy = ExpiryPolicy::new("after-lunch", 60).unwrap();
+    state.remove_old_proposals(policy, now + chrono::Duration::minutes(1));
+    assert_eq!(state.num_of_proposals(), 1);
+    let reminders = state.take_reminders(now, ahead, &preferences, &users);
+    assert!(reminders.is_empty());
+    let reminders = state.take_reminders(tomorrow.and_hms(0, 5, 0), ahead, &preferences, &users);
+    assert_eq!(reminders, vec![": lunch time! #1 kebab at 00:10"]);
+    state.remove_old_proposals(policy, tomorrow.and_hms(1, 9, 0));
+    assert_eq!(state.num_of_proposals(), 1);
+    state.remove_old_proposals(policy, tomorrow.and_hms(1, 10, 0));
+    assert_eq!(state.num_of_proposals(), 0);
+}
+
+#[test]
+fn channels_have_separate_state() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    let jan = Identity::new("jan");
+    let mut run = |line, channel| {
+        update_state(line, channel, &jan, false, states.clone(), &mut storage, &users)
+    };
+    run("group add team jan", "#first");
+    assert_eq!(run("list groups", "#first"), "Groups: team");
+    assert_eq!(run("list groups", "#second"), "Groups: ");
+
+    run("propose winston 12:00", "#first");
+    run("propose pho 12:30", "#first");
+    assert_eq!(run("list", "#second"), "All proposals: []");
+    assert_eq!(run("propose kocka 12:00", "#second"), "New proposal: go to #1 kocka at 12:00");
+    assert_eq!(run("list", "#second"), "All proposals: [#1 kocka at 12:00]");
+    // Channel names are case insensitive
+    assert_eq!(
+        run("list", "#First"),
+        "All proposals: [#1 winston at 12:00, #2 pho at 12:30]"
+    );
+}
+
+#[test]
+fn destructive_commands_need_permission() {
+    use std::cell::RefCell;
+
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    states.lock().unwrap().set_admins(vec!["boss".to_string()]);
+    let users = UsersOnline(vec![]);
+    let storage = RefCell::new(SavedChannels::default());
+    let identity = |nick: &str, user: &str, account: Option<&str>| Identity {
+        nick: nick.to_string(),
+        account: account.map(str::to_string),
+        hostmask: Some(format!("{}!~{}@{}.example.com", nick, user, user)),
+    };
+    let jan = identity("jan", "jan", None);
+    let ondra = identity("ondra", "ondra", None);
+    // Taking the nick of an admin is not enough
+    let fake_boss = identity("boss", "ondra", None);
+    let boss = identity("pepa", "pepa", Some("boss"));
+    let run = |line, sender: &Identity| {
+        let mut storage = storage.borrow_mut();
+        update_state(line, "#lunch", sender, false, states.clone(), &mut *storage, &users)
+    };
+    // Refused commands do not save anything
+    let refused = |line, sender: &Identity| {
+        let saved = storage.borrow().0.len();
+        let reply = run(line, sender);
+        assert_eq!(storage.borrow().0.len(), saved, "`{}` was saved", line);
+        reply
+    };
+
+    run("group add team jan,ondra", &jan);
+    run("group add others pepa", &ondra);
+    run("propose winston 12:00", &jan);
+    run("place add Winston", &jan);
+    assert_eq!(
+        refused("group remove team", &ondra),
+        "Only the owner of group team or an admin can remove it"
+    );
+    assert_eq!(
+        refused("group rename team core", &ondra),
+        "Only the owner of group team or an admin can rename it"
+    );
+    assert_eq!(
+        refused("remove jan from team", &ondra),
+        "Only the owner of group team or an admin can remove others"
+    );
+    assert_eq!(refused("cancel 1", &ondra), "Only whoever proposed #1 or an admin can cancel it");
+    assert_eq!(
+        refused("edit 1 time 12:30", &ondra),
+        "Only whoever proposed #1 or an admin can edit it"
+    );
+    assert_eq!(
+        refused("place remove winston", &ondra),
+        "Only whoever added winston or an admin can remove it"
+    );
+    assert_eq!(run("remove ondra from team", &ondra), "Group team updated: jan");
+    let jan_wfh = identity("jan|wfh", "jan", None);
+    assert_eq!(run("group remove team", &jan_wfh), "Group team has been removed");
+    assert!(run("edit 1 time 12:30", &jan_wfh).starts_with("Proposal updated"));
+    assert_eq!(run("cancel 1", &jan_wfh), "Proposal #1 winston at 12:30 has been cancelled");
+    assert_eq!(refused("dumpstate", &fake_boss), "Only admins can dump the state");
+    assert_eq!(refused("restore {}", &fake_boss), "Only admins can restore the state");
+    assert_eq!(run("group remove others", &boss), "Group others has been removed");
+    assert_eq!(run("place remove winston", &boss), "Place Winston has been removed");
+    assert!(run("dumpstate", &boss).starts_with('{'));
+}
+
+#[test]
+fn restored_dumps_are_migrated() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    states.lock().unwrap().set_admins(vec!["boss".to_string()]);
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    let boss = Identity {
+        account: Some("boss".to_string()),
+        ..Identity::new("pepa")
+    };
+    let mut run = |line: &str| {
+        update_state(line, "#lunch", &boss, true, states.clone(), &mut storage, &users)
+    };
+
+    run("veto kocka");
+    // A dump from before proposals had ids
+    let dump = r##"{"groups": [], "store": 0, "channel": "#old",
+        "proposals": [{"place": "winston", "time": "12.30", "group": null,
+            "meeting_point": null,
+            "created": {"secs_since_epoch": 1530000000, "nanos_since_epoch": 0}}]}"##;
+    assert_eq!(run(&format!("restore {}", dump)), "Success");
+    assert_eq!(run("list"), "All proposals: [#1 winston at 12:30]");
+    // A whole backup brings the preferences along
+    let backup = r##"{"version": 1, "channels": {"#old": {"groups": [], "proposals": [],
+            "store": 0, "channel": "#old"}},
+        "preferences": {"pepa": {"vetoes": ["pho"], "diet": []},
+            "jan": {"vetoes": ["pho"], "diet": []}}}"##;
+    assert_eq!(run(&format!("restore {}", backup)), "Success");
+    let states = states.lock().unwrap();
+    assert_eq!(states.get("#lunch").unwrap().channel, "#lunch");
+    assert_eq!(states.preferences["jan"].vetoes, vec!["pho"]);
+    // Newer preferences are not overwritten by the restore
+    assert_eq!(states.preferences["pepa"].vetoes, vec!["kocka"]);
+}
+
+#[test]
+fn group_members_can_be_managed() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    let jan = Identity {
+        hostmask: Some("jan!~jan@example.com".to_string()),
+        ..Identity::new("jan")
+    };
+    let mut run = |line| {
+        update_state(line, "#lunch", &jan, false, states.clone(), &mut storage, &users)
+    };
+
+    assert_eq!(run("group add team jan,ondra,jan"), "New group: team - jan,ondra");
+    assert!(run("group add team pepa").starts_with("Group team already exists"));
+    assert_eq!(run("add ondra to team"), "ondra already is in group team");
+    assert_eq!(run("add pepa to team"), "Group team updated: jan,ondra,pepa");
+    assert_eq!(run("remove jan from team"), "Group team updated: ondra,pepa");
+    assert_eq!(run("remove jan from team"), "jan is not in group team");
+    run("propose winston 12:00 to team");
+    run("group add others jan");
+    assert_eq!(run("group rename team others"), "Group others already exists");
+    assert_eq!(run("group rename team core"), "Group team is now called core");
+    assert_eq!(run("group show core"), "Group core: ondra,pepa");
+    assert_eq!(run("group show team"), "unknown group `team`");
+    {
+        let states = states.lock().unwrap();
+        let proposal = &states.get("#lunch").unwrap().proposals[0];
+        assert_eq!(proposal.group, Some("core".to_string()));
+    }
+
+    let ondra = Identity::new("ondra|wfh");
+    let mut run = |line| {
+        update_state(line, "#lunch", &ondra, false, states.clone(), &mut storage, &users)
+    };
+    assert_eq!(run("leave-group core"), "ondra left group core: pepa");
+    assert_eq!(run("leave-group core"), "ondra is not in group core");
+    assert_eq!(run("join-group core"), "ondra joined group core: pepa,ondra");
+    assert_eq!(run("join-group core"), "ondra already is in group core");
+    assert_eq!(run("join-group cor"), "unknown group `cor`, did you mean `core`?");
+    let states = states.lock().unwrap();
+    let proposal = &states.get("#lunch").unwrap().proposals[0];
+    assert_eq!(proposal.group, Some("core".to_string()));
+}
+
+#[test]
+fn only_changes_are_saved() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    let jan = Identity::new("jan");
+    let lines = [
+        "group add team jan",
+        "list",
+        "help",
+        "propose winston 12:00",
+        // Commands that fail change nothing
+        "join 99",
+        "propose winston 12:00 to nobody",
+        "group remove nobody",
+        "place remove nowhere",
+    ];
+    for line in &lines {
+        update_state(line, "#lunch", &jan, false, states.clone(), &mut storage, &users);
+    }
+    assert_eq!(storage.0, vec!["#lunch", "#lunch"]);
+}
+
+#[test]
+fn opened_poll_is_saved() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    let jan = Identity::new("jan");
+    for line in &["poll", "poll winston pho", "poll"] {
+        update_state(line, "#lunch", &jan, false, states.clone(), &mut storage, &users);
+    }
+    assert_eq!(storage.0, vec!["#lunch"]);
+}
+
+#[test]
+fn proposals_use_canonical_place_names() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec![]);
+    let mut storage = SavedChannels::default();
+    // Only whoever proposed it can edit a proposal
+    let jan = Identity {
+        hostmask: Some("jan!~jan@example.com".to_string()),
+        ..Identity::new("jan")
+    };
+    let mut run = |line| {
+        update_state(line, "#lunch", &jan, false, states.clone(), &mut storage, &users)
+    };
+    run("place add Winston walk 5");
+    run("place alias winston win");
+    assert_eq!(run("propose 'WIN' 12:00"), "New proposal: go to #1 Winston at 12:00");
+    assert_eq!(run("edit 1 place kocka"), "Proposal updated: #1 kocka at 12:00");
+    assert_eq!(run("edit 1 place winstn"), "Proposal updated: #1 Winston at 12:00");
+    assert_eq!(run("list places"), "Places: Winston");
+    assert_eq!(run("menu win"), "Menu of Winston today:\r\n- soup");
+}
+
+#[test]
+fn suggest_respects_vetoes() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = UsersOnline(vec!["jan|wfh", "ondra"]);
+    let mut storage = SavedChannels::default();
+    // Preferences are private, but everything else works in a private message too
+    let mut run = |line, sender| {
+        let sender = Identity::new(sender);
+        update_state(line, "#lunch", &sender, true, states.clone(), &mut storage, &users)
+    };
+    run("group add team jan,ondra", "jan");
+    run("place add Winston", "jan");
+    run("place add Kocka tags vegan", "jan");
+    assert_eq!(run("veto winston", "jan|wfh"), "Winston will not be suggested to jan");
+    assert_eq!(run("suggest for team", "ondra"), "How about Kocka?");
+    assert_eq!(
+        run("suggest for team at 12:00", "ondra"),
+        "How about Kocka? jan|wfh,ondra go to #1 Kocka at 12:00"
+    );
+
+    assert_eq!(run("diet vegetarian", "ondra"), "ondra eats only at places tagged vegetarian");
+    assert_eq!(
+        run("suggest for team", "ondra"),
+        "There is no place that suits everybody in team"
+    );
+    assert_eq!(run("diet none", "ondra"), "ondra eats anywhere");
+    assert_eq!(run("veto remove Winston", "jan"), "Winston can be suggested to jan again");
+}
+
+#[test]
+fn poll_winner_is_proposed() {
+    let mut states = ChannelStates::new();
+    let users = UsersOnline(vec!["jan|wfh", "ondra"]);
+    // The deadlines must not have passed
+    let now = NaiveTime::from_hms(11, 0, 0);
+    {
+        let mut run = |line, sender| {
+            let sender = Identity::new(sender);
+            run_command(parse_command(line), "#lunch", &sender, &mut states, now, &users)
+        };
+        run("group add team jan,ondra", "jan");
+        run("place add Winston", "jan");
+        assert_eq!(
+            run("poll winstn pho winston by 11:30 at 11:45 to team", "jan"),
+            "Poll until 11:30: 1) Winston (0), 2) pho (0) - vote with `lb vote <number>`"
+        );
+        assert_eq!(run("vote 3", "jan"), "There is no option 3 in the poll");
+        run("vote 1", "jan|wfh");
+        run("vote 2", "ondra");
+        assert_eq!(run("vote 2", "jan"), "jan voted for pho: 1) Winston (0), 2) pho (2)");
+        assert_eq!(run("poll", "ondra"), "Poll until 11:30: 1) Winston (0), 2) pho (2)");
+        assert!(run("poll kocka pho", "ondra").starts_with("A poll is already open"));
+    }
+
+    let state = states.get_or_insert("#lunch");
+    let created = Local::today().and_hms(9, 0, 0);
+    state.poll.as_mut().unwrap().created = created.into();
+    let today = created.date();
+    assert_eq!(state.close_poll(today.and_hms(11, 29, 0), &users), None);
+    assert_eq!(
+        state.close_poll(today.and_hms(11, 30, 0), &users),
+        Some(
+            "The poll is closed, pho won: 1) Winston (0), 2) pho (2)\r\n\
+             jan|wfh,ondra go to #1 pho at 11:45"
+                .to_string()
+        )
+    );
+    assert_eq!(state.poll, None);
+}
+
+#[test]
+fn poll_deadline_must_be_ahead() {
+    let mut state = LunchBotState::new("#lunch");
+    let places = vec!["winston".to_string(), "pho".to_string()];
+    let at = |hour, minute| NaiveTime::from_hms(hour, minute, 0);
+    assert_eq!(
+        state.open_poll(places.clone(), at(11, 30), at(11, 45), None, at(11, 45)),
+        "The deadline 11:30 has already passed"
+    );
+    assert_eq!(state.poll, None);
+    assert!(state
+        .open_poll(places, at(11, 30), at(11, 45), None, at(11, 0))
+        .starts_with("Poll until 11:30"));
+}
+
+#[test]
+fn late_polls_close_before_midnight() {
+    let mut states = ChannelStates::new();
+    let users = UsersOnline(vec![]);
+    let jan = Identity::new("jan");
+    let mut run = |line, now| {
+        run_command(parse_command(line), "#lunch", &jan, &mut states, now, &users)
+    };
+    assert_eq!(
+        run("poll winston pho", NaiveTime::from_hms(23, 45, 0)),
+        "Poll until 23:59: 1) winston (0), 2) pho (0) - vote with `lb vote <number>`"
+    );
+    let state = states.get_or_insert("#lunch");
+    assert_eq!(state.poll.as_ref().unwrap().deadline, NaiveTime::from_hms(23, 59, 59));
+    assert_eq!(default_deadline(NaiveTime::from_hms(11, 0, 0)), NaiveTime::from_hms(11, 30, 0));
+}
+
+/// Remembers private messages
+#[cfg(test)]
+struct Notified(Vec<&'static str>, ::std::cell::RefCell<Vec<String>>);
+
+#[cfg(test)]
+impl StateUpdateCallbacks for Notified {
+    fn get_list_of_users(&self, _channel: &str) -> Vec<User> {
+        self.0.iter().map(|u| u.to_string()).collect()
+    }
+
+    fn menu(&self, _place: &str) -> Option<String> {
+        None
+    }
+
+    fn notify(&self, nick: &str, message: &str) {
+        self.1.borrow_mut().push(format!("{} <- {}", nick, message));
+    }
+}
+
+#[test]
+fn private_messages_name_the_channel() {
+    let one = vec!["#lunch".to_string()];
+    let two = vec!["#lunch".to_string(), "#dinner".to_string()];
+    assert_eq!(private_channel("list", &one), Ok(("#lunch", "list")));
+    assert_eq!(private_channel("#Dinner list", &two), Ok(("#dinner", "list")));
+    assert_eq!(private_channel("#pub list", &two), Err("I am not in #pub".to_string()));
+    assert_eq!(
+        private_channel("list", &two),
+        Err("I am in #lunch, #dinner, say which channel the command is for, like `#lunch list`"
+            .to_string())
+    );
+    // Preferences are shared by all channels
+    assert_eq!(private_channel("veto pho", &two), Ok(("#lunch", "veto pho")));
+    assert_eq!(private_channel("help", &two), Ok(("#lunch", "help")));
+}
+
+#[test]
+fn subscribers_are_reminded_privately() {
+    let states = Arc::new(Mutex::new(ChannelStates::new()));
+    let users = Notified(vec!["jan|wfh", "ondra"], Default::default());
+    let mut storage = SavedChannels::default();
+    // Private messages go to the first channel, but preferences are shared by all of them
+    let mut run = |line, private| {
+        let sender = Identity::new("jan|wfh");
+        let channel = if private { "#first" } else { "#lunch" };
+        update_state(line, channel, &sender, private, states.clone(), &mut storage, &users)
+    };
+    assert_eq!(
+        run("subscribe", false),
+        "This command only works in a private message to me"
+    );
+    assert_eq!(run("subscribe", true), "jan will get reminders in a private message too");
+    run("group add team jan,ondra", false);
+    run("propose winston 12:00 to team", false);
+
+    let mut states = states.lock().unwrap();
+    let (mut channels, preferences) = states.states_and_preferences_mut();
+    let state = channels.find(|state| state.channel() == "#lunch").unwrap();
+    let ahead = chrono::Duration::minutes(10);
+    let now = Local::today().and_hms(11, 55, 0);
+    // Proposed in the morning, otherwise the lunch would be tomorrow
+    state.proposals[0].created = Local::today().and_hms(9, 0, 0).into();
+    let reminders = state.take_reminders(now, ahead, preferences, &users);
+    assert_eq!(reminders, vec!["jan|wfh,ondra: lunch time! #1 winston at 12:00"]);
+    assert_eq!(
+        *users.1.borrow(),
+        vec!["jan|wfh <- jan|wfh,ondra: lunch time! #1 winston at 12:00"]
+    );
 }
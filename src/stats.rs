@@ -0,0 +1,242 @@
+use chrono::{Datelike, NaiveDate, NaiveTime, Timelike, Weekday};
+use state::{base_nick, Lunch};
+use std::collections::{BTreeMap, BTreeSet};
+use syntax::StatsOptions;
+
+const TOP: usize = 5;
+
+pub fn stats(history: &[Lunch], option: StatsOptions, today: NaiveDate) -> String {
+    if history.is_empty() {
+        return "No lunches recorded yet".to_string();
+    }
+    match option {
+        StatsOptions::Overview => [
+            places(history, today),
+            people(history, None),
+            average_time(history),
+            streaks(history, today),
+        ].join("\r\n"),
+        StatsOptions::Places => places(history, today),
+        StatsOptions::People(nick) => people(history, nick.as_ref().map(String::as_str)),
+        StatsOptions::Time => average_time(history),
+        StatsOptions::Streaks => streaks(history, today),
+    }
+}
+
+/// The most frequent keys first, ties in alphabetical order
+fn top<K: Ord>(counts: BTreeMap<K, u32>) -> Vec<(K, u32)> {
+    let mut counts: Vec<(K, u32)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(TOP);
+    counts
+}
+
+fn places(history: &[Lunch], today: NaiveDate) -> String {
+    let mut counts = BTreeMap::new();
+    for lunch in history
+        .iter()
+        .filter(|l| l.date.year() == today.year() && l.date.month() == today.month())
+    {
+        *counts.entry(lunch.place.as_str()).or_insert(0) += 1;
+    }
+    if counts.is_empty() {
+        return "No lunches recorded this month".to_string();
+    }
+    let places: Vec<String> = top(counts)
+        .into_iter()
+        .map(|(place, count)| format!("{} ({})", place, count))
+        .collect();
+    format!("Most popular places in {}: {}", today.format("%B"), places.join(", "))
+}
+
+/// Counts lunches of every pair of attendees, or of `nick` with everybody else. People are
+/// counted by their base nick, older lunches recorded whatever nick they joined with.
+fn people(history: &[Lunch], nick: Option<&str>) -> String {
+    let nick = nick.map(base_nick);
+    let mut counts = BTreeMap::new();
+    for lunch in history {
+        let attendees: BTreeSet<&str> = lunch.attendees.iter().map(|a| base_nick(a)).collect();
+        for &a in &attendees {
+            for &b in attendees.range(a..).skip(1) {
+                match nick {
+                    None => *counts.entry(format!("{} & {}", a, b)).or_insert(0) += 1,
+                    Some(nick) if nick == a => *counts.entry(b.to_string()).or_insert(0) += 1,
+                    Some(nick) if nick == b => *counts.entry(a.to_string()).or_insert(0) += 1,
+                    Some(_) => (),
+                }
+            }
+        }
+    }
+
+    let companions: Vec<String> = top(counts)
+        .into_iter()
+        .map(|(people, count)| format!("{} ({})", people, count))
+        .collect();
+    match nick {
+        _ if companions.is_empty() => match nick {
+            Some(nick) => format!("{} has not been to lunch with anyone yet", nick),
+            None => "Nobody has been to lunch together yet".to_string(),
+        },
+        Some(nick) => format!("{} goes to lunch most often with {}", nick, companions.join(", ")),
+        None => format!("Most frequent lunch companions: {}", companions.join(", ")),
+    }
+}
+
+fn average_time(history: &[Lunch]) -> String {
+    // The seconds of some 100 000 lunches do not fit in a u32
+    let total: u64 = history
+        .iter()
+        .map(|l| u64::from(l.time.num_seconds_from_midnight()))
+        .sum();
+    let average = total / history.len() as u64;
+    let time = NaiveTime::from_num_seconds_from_midnight(average as u32, 0);
+    format!(
+        "Average lunch time: {} over {} lunches",
+        time.format("%H:%M"),
+        history.len()
+    )
+}
+
+fn previous_working_day(date: NaiveDate) -> NaiveDate {
+    let mut date = date.pred();
+    while date.weekday() == Weekday::Sat || date.weekday() == Weekday::Sun {
+        date = date.pred();
+    }
+    date
+}
+
+/// Working days in a row with a lunch of the group, the current streak is not broken until the
+/// previous working day goes by without one
+fn streak_of(dates: &BTreeSet<NaiveDate>, today: NaiveDate) -> (u32, u32) {
+    let mut longest = 0;
+    let mut length = 0;
+    let mut last = None;
+    for &date in dates {
+        length = match last {
+            Some(last) if previous_working_day(date) == last => length + 1,
+            _ => 1,
+        };
+        longest = longest.max(length);
+        last = Some(date);
+    }
+
+    let mut day = if dates.contains(&today) {
+        today
+    } else {
+        previous_working_day(today)
+    };
+    let mut current = 0;
+    while dates.contains(&day) {
+        current += 1;
+        day = previous_working_day(day);
+    }
+    (current, longest)
+}
+
+fn streaks(history: &[Lunch], today: NaiveDate) -> String {
+    let mut dates: BTreeMap<&str, BTreeSet<NaiveDate>> = BTreeMap::new();
+    for lunch in history {
+        if let Some(ref group) = lunch.group {
+            dates.entry(group.as_str()).or_default().insert(lunch.date);
+        }
+    }
+    if dates.is_empty() {
+        return "No group lunches recorded yet".to_string();
+    }
+    let streaks: Vec<String> = dates
+        .iter()
+        .map(|(group, dates)| {
+            let (current, longest) = streak_of(dates, today);
+            format!("{} {} (longest {})", group, current, longest)
+        })
+        .collect();
+    format!("Streaks in working days: {}", streaks.join(", "))
+}
+
+#[cfg(test)]
+fn lunch(day: u32, place: &str, group: Option<&str>, attendees: &[&str]) -> Lunch {
+    Lunch {
+        date: NaiveDate::from_ymd(2018, 7, day),
+        place: place.to_string(),
+        time: if day % 2 == 0 {
+            NaiveTime::from_hms(11, 30, 0)
+        } else {
+            NaiveTime::from_hms(12, 0, 0)
+        },
+        group: group.map(String::from),
+        attendees: attendees.iter().map(|a| a.to_string()).collect(),
+    }
+}
+
+#[cfg(test)]
+fn history() -> Vec<Lunch> {
+    vec![
+        lunch(30, "winston", Some("team"), &["jan", "ondra", "pepa"]),
+        lunch(31, "kocka", Some("team"), &["jan|wfh", "ondra"]),
+        lunch(31, "winston", None, &["karel"]),
+    ]
+}
+
+#[test]
+fn popular_places_this_month() {
+    let today = NaiveDate::from_ymd(2018, 7, 31);
+    assert_eq!(
+        stats(&history(), StatsOptions::Places, today),
+        "Most popular places in July: winston (2), kocka (1)"
+    );
+    let next_month = NaiveDate::from_ymd(2018, 8, 1);
+    assert_eq!(
+        stats(&history(), StatsOptions::Places, next_month),
+        "No lunches recorded this month"
+    );
+}
+
+#[test]
+fn lunch_companions() {
+    let today = NaiveDate::from_ymd(2018, 7, 31);
+    assert_eq!(
+        stats(&history(), StatsOptions::People(None), today),
+        "Most frequent lunch companions: jan & ondra (2), jan & pepa (1), ondra & pepa (1)"
+    );
+    assert_eq!(
+        stats(&history(), StatsOptions::People(Some("pepa|afk".into())), today),
+        "pepa goes to lunch most often with jan (1), ondra (1)"
+    );
+    assert_eq!(
+        stats(&history(), StatsOptions::People(Some("karel".into())), today),
+        "karel has not been to lunch with anyone yet"
+    );
+}
+
+#[test]
+fn average_lunch_time() {
+    let today = NaiveDate::from_ymd(2018, 7, 31);
+    assert_eq!(
+        stats(&history(), StatsOptions::Time, today),
+        "Average lunch time: 11:50 over 3 lunches"
+    );
+    let years_of_lunches = vec![lunch(31, "winston", None, &[]); 100_000];
+    assert_eq!(
+        stats(&years_of_lunches, StatsOptions::Time, today),
+        "Average lunch time: 12:00 over 100000 lunches"
+    );
+}
+
+#[test]
+fn group_streaks_skip_weekends() {
+    // Friday 27th, Monday 30th and Tuesday 31st
+    let mut history = history();
+    history.push(lunch(27, "winston", Some("team"), &[]));
+    history.push(lunch(20, "winston", Some("team"), &[]));
+
+    let wednesday = NaiveDate::from_ymd(2018, 8, 1);
+    assert_eq!(
+        stats(&history, StatsOptions::Streaks, wednesday),
+        "Streaks in working days: team 3 (longest 3)"
+    );
+    let thursday = NaiveDate::from_ymd(2018, 8, 2);
+    assert_eq!(
+        stats(&history, StatsOptions::Streaks, thursday),
+        "Streaks in working days: team 0 (longest 3)"
+    );
+}
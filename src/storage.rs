@@ -1,34 +1,281 @@
 use failure::Error;
-use serde_json;
-use state::LunchBotState;
-use std::fs::File;
+use migration::{migrate, CURRENT_VERSION};
+use serde_json::{self, Value};
+use state::{ChannelStates, Proposal};
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The state is saved after every command, older backups are kept from at least this long ago
+const ROTATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Where the state is kept, so that the bot can continue after a restart
+pub trait Storage {
+    /// Replaces the channels in `states` with the stored ones
+    fn load(&mut self, states: &mut ChannelStates) -> Result<(), Error>;
+    /// Called after every change of the state of `channel`
+    fn save(&mut self, states: &ChannelStates, channel: &str) -> Result<(), Error>;
+    /// Called with proposals that expired and were removed from `channel`
+    fn archive(&mut self, channel: &str, proposals: &[Proposal]) -> Result<(), Error>;
+}
+
+/// Keeps the state in memory only
+pub struct NoStorage;
+
+impl Storage for NoStorage {
+    fn load(&mut self, _states: &mut ChannelStates) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn save(&mut self, _states: &ChannelStates, _channel: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn archive(&mut self, _channel: &str, _proposals: &[Proposal]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Snapshots of all channels in a JSON file, see `backup_state`
+pub struct JsonFile {
+    file_name: PathBuf,
+    generations: u32,
+    rotation_interval: Duration,
+    /// None until the first save, which keeps the backup of the previous run
+    rotated_at: Option<Instant>,
+}
+
+impl JsonFile {
+    pub fn new(file_name: &Path, generations: u32) -> JsonFile {
+        JsonFile {
+            file_name: file_name.to_path_buf(),
+            generations,
+            rotation_interval: ROTATION_INTERVAL,
+            rotated_at: None,
+        }
+    }
+}
+
+impl Storage for JsonFile {
+    fn load(&mut self, states: &mut ChannelStates) -> Result<(), Error> {
+        recover_state(states, &self.file_name, self.generations)
+    }
+
+    fn save(&mut self, states: &ChannelStates, _channel: &str) -> Result<(), Error> {
+        let interval = self.rotation_interval;
+        let rotate = self.rotated_at.map_or(true, |at| at.elapsed() >= interval);
+        backup_state(states, &self.file_name, self.generations, rotate)?;
+        if rotate {
+            self.rotated_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Only the current state is kept in the snapshots
+    fn archive(&mut self, _channel: &str, _proposals: &[Proposal]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Path of an older backup, the newest one (0) is the file itself
+fn generation(file_name: &Path, n: u32) -> PathBuf {
+    if n == 0 {
+        return file_name.to_path_buf();
+    }
+    let mut name = OsString::from(file_name.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Writes the state next to the backup first, so that a crash never leaves a half written file
+/// behind. With `rotate` the previous backups are shifted by one and `generations` of them are
+/// kept, otherwise the newest one is replaced.
+pub fn backup_state(
+    states: &ChannelStates,
+    file_name: &Path,
+    generations: u32,
+    rotate: bool,
+) -> Result<(), Error> {
+    let mut tmp_name = OsString::from(file_name.as_os_str());
+    tmp_name.push(".tmp");
+    let tmp_name = PathBuf::from(tmp_name);
+
+    let mut snapshot = serde_json::to_value(states)?;
+    if let Some(map) = snapshot.as_object_mut() {
+        map.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    }
+    let mut f = File::create(&tmp_name)?;
+    f.write_all(serde_json::to_string(&snapshot)?.as_bytes())?;
+    f.sync_all()?;
 
-pub fn backup_state(state: &LunchBotState, file_name: &Path) -> Result<(), Error> {
-    let mut f = File::create(file_name)?;
-    f.write_all(serde_json::to_string(&state)?.as_bytes())?;
+    let shifted = if rotate { generations } else { 1 };
+    for n in (1..shifted).rev() {
+        let older = generation(file_name, n - 1);
+        if older.exists() {
+            fs::rename(&older, generation(file_name, n))?;
+        }
+    }
+    fs::rename(&tmp_name, file_name)?;
+
+    // Make the renames themselves durable
+    let dir = match file_name.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
     Ok(())
 }
 
-pub fn recover_state(state: &mut LunchBotState, file_name: &Path) -> Result<(), Error> {
+fn read_state(file_name: &Path) -> Result<ChannelStates, Error> {
     let mut f = File::open(file_name)?;
     let mut contents = String::new();
     f.read_to_string(&mut contents)?;
-    *state = serde_json::from_str(&contents)?;
-    Ok(())
+    let snapshot = migrate(serde_json::from_str(&contents)?)?;
+    Ok(serde_json::from_value(snapshot)?)
+}
+
+/// Uses the newest backup that can be read, the error of the newest one is returned if none can
+pub fn recover_state(
+    states: &mut ChannelStates,
+    file_name: &Path,
+    generations: u32,
+) -> Result<(), Error> {
+    let mut first_error = None;
+    for n in 0..generations.max(1) {
+        let path = generation(file_name, n);
+        match read_state(&path) {
+            Ok(recovered) => {
+                if n > 0 {
+                    warn!("Recovered the state from an older backup {}", path.display());
+                }
+                *states = recovered;
+                return Ok(());
+            }
+            Err(e) => {
+                if path.exists() {
+                    error!("Backup {} is not usable: {}", path.display(), e);
+                }
+                first_error = first_error.or(Some(e));
+            }
+        }
+    }
+    Err(first_error.expect("at least one generation is tried"))
+}
+
+#[cfg(test)]
+use state::LunchBotState;
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = ::std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
 }
 
 #[test]
 fn backup_and_recover() {
-    use state::LunchBotState;
+    let mut states = ChannelStates::new();
+    states.get_or_insert("#ahoj");
+    states.get_or_insert("#nazdar");
+    let file_name = test_dir("lunch-bot-backup-and-recover").join("backup");
+    backup_state(&states, &file_name, 3, true).unwrap();
+
+    let mut states2 = ChannelStates::new();
+    recover_state(&mut states2, &file_name, 3).unwrap();
 
+    assert_eq!(states, states2);
+}
+
+#[test]
+fn recover_single_channel_backup() {
     let state = LunchBotState::new("#ahoj");
-    let file_name = Path::new("/tmp/lunch-bot-test-file");
-    let _ = backup_state(&state, &file_name);
+    let file_name = test_dir("lunch-bot-single-channel").join("backup");
+    let mut f = File::create(&file_name).unwrap();
+    f.write_all(serde_json::to_string(&state).unwrap().as_bytes())
+        .unwrap();
+
+    let mut states = ChannelStates::new();
+    recover_state(&mut states, &file_name, 1).unwrap();
+
+    let mut expected = ChannelStates::new();
+    expected.insert(state);
+    assert_eq!(states, expected);
+}
+
+#[test]
+fn backups_are_rotated() {
+    let dir = test_dir("lunch-bot-rotation");
+    let file_name = dir.join("backup");
+    let mut states = ChannelStates::new();
+    for channel in &["#1", "#2", "#3", "#4"] {
+        states.get_or_insert(channel);
+        backup_state(&states, &file_name, 3, true).unwrap();
+    }
+
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["backup", "backup.1", "backup.2"]);
+
+    let mut oldest = read_state(&generation(&file_name, 2)).unwrap();
+    assert_eq!(oldest.states_and_preferences_mut().0.count(), 2);
+}
+
+#[test]
+fn saves_rotate_backups_once_per_interval() {
+    let dir = test_dir("lunch-bot-rotation-interval");
+    let file_name = dir.join("backup");
+    let mut storage = JsonFile::new(&file_name, 3);
+    let mut states = ChannelStates::new();
+    let files = || {
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    };
+
+    for channel in &["#1", "#2", "#3"] {
+        states.get_or_insert(channel);
+        storage.save(&states, channel).unwrap();
+    }
+    assert_eq!(files(), vec!["backup"]);
+    let mut newest = read_state(&file_name).unwrap();
+    assert_eq!(newest.states_and_preferences_mut().0.count(), 3);
+
+    // Once the interval passes, the next save keeps the previous backup
+    storage.rotation_interval = Duration::from_secs(0);
+    states.get_or_insert("#4");
+    storage.save(&states, "#4").unwrap();
+    assert_eq!(files(), vec!["backup", "backup.1"]);
+    let mut previous = read_state(&generation(&file_name, 1)).unwrap();
+    assert_eq!(previous.states_and_preferences_mut().0.count(), 3);
+}
+
+#[test]
+fn recover_falls_back_to_older_backup() {
+    let file_name = test_dir("lunch-bot-fallback").join("backup");
+    let mut states = ChannelStates::new();
+    states.get_or_insert("#ahoj");
+    backup_state(&states, &file_name, 3, true).unwrap();
+    states.get_or_insert("#nazdar");
+    backup_state(&states, &file_name, 3, true).unwrap();
 
-    let mut state2 = LunchBotState::new("");
-    let _ = recover_state(&mut state2, &file_name);
+    // A crash in the middle of writing, as it used to happen before atomic backups
+    File::create(&file_name)
+        .unwrap()
+        .write_all(b"{\"channels\": {\"#ah")
+        .unwrap();
 
-    assert_eq!(state, state2);
+    let mut recovered = ChannelStates::new();
+    recover_state(&mut recovered, &file_name, 3).unwrap();
+    let mut expected = ChannelStates::new();
+    expected.get_or_insert("#ahoj");
+    assert_eq!(recovered, expected);
 }
@@ -0,0 +1,151 @@
+use chrono::{Duration, NaiveDate};
+use places::{normalize, Catalogue};
+use rand::Rng;
+use state::Lunch;
+
+/// Default of `recent_days`
+pub const RECENT_DAYS: i64 = 3;
+
+/// Picks a place that nobody `vetoes` and that has all the `diet` tags. Places where people go
+/// often are more likely, but new ones have a chance too. Places visited in the last
+/// `recent_days` are only suggested when there is nothing else left.
+pub fn suggest<R: Rng>(
+    places: &Catalogue,
+    history: &[Lunch],
+    vetoes: &[&str],
+    diet: &[&str],
+    today: NaiveDate,
+    recent_days: i64,
+    rng: &mut R,
+) -> Option<String> {
+    // Without a catalogue at least the places from the history can be suggested
+    let mut candidates: Vec<String> = places.places().iter().map(|p| p.name.clone()).collect();
+    if candidates.is_empty() {
+        for lunch in history {
+            if !candidates.iter().any(|c| normalize(c) == normalize(&lunch.place)) {
+                candidates.push(lunch.place.clone());
+            }
+        }
+    }
+
+    let vetoes: Vec<String> = vetoes.iter().map(|v| normalize(&places.canonical(v))).collect();
+    candidates.retain(|name| {
+        let suits_diet = match places.resolve(name) {
+            Some(place) => diet.iter().all(|tag| place.has_tag(tag)),
+            None => diet.is_empty(),
+        };
+        suits_diet && !vetoes.contains(&normalize(name))
+    });
+
+    let visits = |name: &str, since: NaiveDate| {
+        let name = normalize(name);
+        history
+            .iter()
+            .filter(|l| l.date > since && normalize(&places.canonical(&l.place)) == name)
+            .count() as u32
+    };
+    let recently = today - Duration::days(recent_days);
+    let fresh: Vec<String> = candidates
+        .iter()
+        .filter(|name| visits(name, recently) == 0)
+        .cloned()
+        .collect();
+    if !fresh.is_empty() {
+        candidates = fresh;
+    }
+
+    let weights: Vec<u32> = candidates
+        .iter()
+        .map(|name| 1 + visits(name, NaiveDate::from_ymd(1, 1, 1)))
+        .collect();
+    let total: u32 = weights.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut pick = rng.gen_range(0, total);
+    for (name, weight) in candidates.into_iter().zip(weights) {
+        if pick < weight {
+            return Some(name);
+        }
+        pick -= weight;
+    }
+    None
+}
+
+#[cfg(test)]
+use chrono::NaiveTime;
+#[cfg(test)]
+use places::Place;
+#[cfg(test)]
+use rand::{SeedableRng, XorShiftRng};
+
+#[cfg(test)]
+fn lunch(date: NaiveDate, place: &str) -> Lunch {
+    Lunch {
+        date,
+        place: place.to_string(),
+        time: NaiveTime::from_hms(12, 0, 0),
+        group: None,
+        attendees: vec![],
+    }
+}
+
+#[cfg(test)]
+fn catalogue() -> Catalogue {
+    let mut catalogue = Catalogue::default();
+    catalogue.add(Place::new("Winston")).unwrap();
+    catalogue.add(Place::new("Pho")).unwrap();
+    let mut kocka = Place::new("Kocka");
+    kocka.tags = vec!["vegan".to_string()];
+    catalogue.add(kocka).unwrap();
+    catalogue
+}
+
+#[test]
+fn vetoes_and_diets_are_respected() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let (today, days) = (NaiveDate::from_ymd(2018, 7, 31), RECENT_DAYS);
+    let catalogue = catalogue();
+    for _ in 0..20 {
+        let place = suggest(&catalogue, &[], &["winston", "pho"], &[], today, days, &mut rng);
+        assert_eq!(place, Some("Kocka".to_string()));
+        let place = suggest(&catalogue, &[], &[], &["Vegan"], today, days, &mut rng);
+        assert_eq!(place, Some("Kocka".to_string()));
+    }
+    assert_eq!(suggest(&catalogue, &[], &["kocka"], &["vegan"], today, days, &mut rng), None);
+}
+
+#[test]
+fn recent_places_are_avoided() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let (today, days) = (NaiveDate::from_ymd(2018, 7, 31), RECENT_DAYS);
+    let catalogue = catalogue();
+    let history = vec![lunch(today.pred(), "winston"), lunch(today, "kocka")];
+    for _ in 0..20 {
+        let place = suggest(&catalogue, &history, &[], &[], today, days, &mut rng);
+        assert_eq!(place, Some("Pho".to_string()));
+    }
+    // Better a recent place than none
+    let place = suggest(&catalogue, &history, &["pho"], &["vegan"], today, days, &mut rng);
+    assert_eq!(place, Some("Kocka".to_string()));
+    // Yesterday is not recent any more
+    let place = suggest(&catalogue, &history, &["pho"], &[], today, 1, &mut rng);
+    assert_eq!(place, Some("Winston".to_string()));
+}
+
+#[test]
+fn popular_places_are_more_likely() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let (today, days) = (NaiveDate::from_ymd(2018, 7, 31), RECENT_DAYS);
+    let long_ago = NaiveDate::from_ymd(2018, 6, 1);
+    // Without a catalogue the places come from the history
+    let mut history = vec![lunch(long_ago, "pho")];
+    history.extend((0..8).map(|_| lunch(long_ago, "winston")));
+
+    let winston = (0..1000)
+        .filter_map(|_| suggest(&Catalogue::default(), &history, &[], &[], today, days, &mut rng))
+        .filter(|place| place == "winston")
+        .count();
+    // Winston has a weight of 9, pho of 2
+    assert!(winston > 750 && winston < 900, "winston suggested {} times", winston);
+}
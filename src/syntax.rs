@@ -1,246 +1,1092 @@
-use regex::{Captures, Regex};
+use failure::Fail;
+use std::cmp;
+use std::fmt;
 use std::str::FromStr;
+use std::vec;
+use places::Place;
+use time::{parse_time, LunchTime, TimeError};
 
-const PROPOSE_SYNTAX: &'static str = concat!(
-    r"lb propose ",                      // command
-    r#"((?:[\w-]+|['"][\s\w-]+['"])) "#, // place
-    r"(?:at |@ )?",                      // optional separator
-    r"([\w:]+)",                         // time
-    r"(?: to (\w+))?",                    // optional group
-    r#"(?: meet ((?:[\w-]+|['"][\s\w-]+['"])) ([\w:]+))?"#,  // optional meeting point
-    r"\s*"
-);
-
-lazy_static! {
-    static ref ADD_CMD_REGEX: Regex = Regex::new(r"lb add (\d+)").unwrap();
-    static ref ADD_USER_CMD_REGEX: Regex = Regex::new(r"lb add (\w+) to (\w+)").unwrap();
-    static ref GROUP_CMD_REGEX: Regex =
-        Regex::new(r"lb group (?:(add) (\w+) ([\w,]+)|(remove) (\w+))").unwrap();
-    static ref PROPOSE_CMD_REGEX: Regex = Regex::new(PROPOSE_SYNTAX).unwrap();
-    static ref LIST_CMD_REGEX: Regex = Regex::new(r"lb list(?: (groups|proposals))?").unwrap();
-    static ref DUMPSTATE_CMD_REGEX: Regex = Regex::new(r"lb dumpstate").unwrap();
-    static ref RESTORECONFIG_CMD_REGEX: Regex = Regex::new(r"lb restore (.*)").unwrap();
-}
+/// All commands together with their usage
+pub const COMMANDS: &'static [(&'static str, &'static str)] = &[
+    ("propose", "lb propose <place>[ at] <time>[ to <group>][ meet <place> <time>]"),
+    ("list", "lb list (groups|proposals|places)"),
+    (
+        "group",
+        "lb group (add <group-name> <comma-separated-list-of-users>|remove <group-name>\
+         |rename <group-name> <new-name>|show <group-name>)",
+    ),
+    ("add", "lb add <user> to <group>"),
+    ("remove", "lb remove <user> from <group>"),
+    ("join-group", "lb join-group <group>"),
+    ("leave-group", "lb leave-group <group>"),
+    ("join", "lb join <proposal-id>"),
+    ("leave", "lb leave <proposal-id>"),
+    ("cancel", "lb cancel <proposal-id>"),
+    ("edit", "lb edit <proposal-id> (place <place>|time <time>)"),
+    (
+        "place",
+        "lb place (add <name>[ address <address>][ walk <minutes>][ notes <notes>]\
+         [ tags <comma-separated-tags>]|alias <place> <alias>|tag <place> <comma-separated-tags>\
+         |remove <place>|info <place>)",
+    ),
+    ("suggest", "lb suggest[ for <group>][ at <time>]"),
+    ("poll", "lb poll[ <place> <place>...[ by <time>][ at <time>][ to <group>]]"),
+    ("vote", "lb vote <option-number>"),
+    ("veto", "lb veto[ remove] <place>"),
+    ("diet", "lb diet (<comma-separated-tags>|none)"),
+    ("subscribe", "lb subscribe"),
+    ("unsubscribe", "lb unsubscribe"),
+    ("menu", "lb menu <place>"),
+    ("stats", "lb stats [places|people [<nick>]|time|streaks]"),
+    ("dumpstate", "lb dumpstate"),
+    ("restore", "lb restore <state-json>"),
+    ("help", "lb help [<command>]"),
+];
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ListOptions {
     Groups,
     Proposals,
+    Places,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PlaceCommand {
+    Add(Place),
+    // place, alias
+    Alias(String, String),
+    Tag(String, Vec<String>),
+    Remove(String),
+    Info(String),
 }
 
+/// Statistics about past lunches, the overview has a line of each
 #[derive(Debug, Eq, PartialEq)]
-pub enum LunchCommand<'a> {
+pub enum StatsOptions {
+    Overview,
+    Places,
+    People(Option<String>),
+    Time,
+    Streaks,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ProposalEdit {
+    Place(String),
+    Time(LunchTime),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LunchCommand {
     Add(u32),
-    AddUser(&'a str, &'a str),
-    GroupAdd(&'a str, Vec<&'a str>),
-    GroupRemove(&'a str),
+    AddUser(String, String),
+    // user, group
+    RemoveUser(String, String),
+    GroupAdd(String, Vec<String>),
+    GroupRemove(String),
+    // old name, new name
+    GroupRename(String, String),
+    GroupShow(String),
+    // Group of the sender
+    JoinGroup(String),
+    LeaveGroup(String),
     List(ListOptions),
     //(place, time, group, meeting point)
-    Propose(&'a str, &'a str, Option<&'a str>, Option<(&'a str, &'a str)>),
+    Propose(String, LunchTime, Option<String>, Option<(String, LunchTime)>),
     DumpState,
-    RestoreState(&'a str),
+    RestoreState(String),
+    // proposal id as shown by `lb list proposals`
+    Join(u32),
+    Leave(u32),
+    Cancel(u32),
+    Edit(u32, ProposalEdit),
+    Place(PlaceCommand),
+    Menu(String),
+    // group, time of the proposal to create
+    Suggest(Option<String>, Option<LunchTime>),
+    Veto(String),
+    Unveto(String),
+    // places, deadline, lunch time, group; without places the open poll is shown
+    Poll(Vec<String>, Option<LunchTime>, Option<LunchTime>, Option<String>),
+    // option number as shown by `lb poll`
+    Vote(u32),
+    // tags that places have to have, none for everything
+    Diet(Vec<String>),
+    Subscribe,
+    Unsubscribe,
+    Stats(StatsOptions),
+    // optional command to describe in detail
+    Help(Option<String>),
+}
+
+impl LunchCommand {
+    /// Personal settings are not changed in the channel
+    pub fn is_private(&self) -> bool {
+        match *self {
+            LunchCommand::Veto(_)
+            | LunchCommand::Unveto(_)
+            | LunchCommand::Diet(_)
+            | LunchCommand::Subscribe
+            | LunchCommand::Unsubscribe => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// Nothing but the prefix
+    MissingCommand,
+    /// The command itself and possibly a similar known command
+    UnknownCommand(String, Option<&'static str>),
+    /// The command was recognized, but its arguments do not match its usage
+    InvalidArguments(&'static str, &'static str),
+    /// The command was recognized, but one of its arguments is out of range
+    InvalidArgument {
+        command: &'static str,
+        argument: &'static str,
+        value: String,
+    },
+    InvalidTime(TimeError),
+    /// A quoted argument without its closing quote
+    UnterminatedQuote,
 }
 
-fn add(caps: Captures) -> Option<LunchCommand> {
-    let arg = caps.get(1)?.as_str();
-    Some(LunchCommand::Add(u32::from_str(arg).ok()?))
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::MissingCommand => write!(f, "missing command"),
+            ParseError::UnknownCommand(ref command, Some(suggestion)) => write!(
+                f,
+                "unknown command `{}`, did you mean `{}`?",
+                command, suggestion
+            ),
+            ParseError::UnknownCommand(ref command, None) => write!(
+                f,
+                "unknown command `{}`, try `lb help` for the list of commands",
+                command
+            ),
+            ParseError::InvalidArguments(command, usage) => {
+                write!(f, "invalid arguments for `{}`, usage: {}", command, usage)
+            }
+            ParseError::InvalidArgument {
+                command,
+                argument,
+                ref value,
+            } => write!(f, "{} `{}` is invalid for `{}`", argument, value, command),
+            ParseError::InvalidTime(ref e) => write!(f, "{}", e),
+            ParseError::UnterminatedQuote => write!(f, "missing closing quote"),
+        }
+    }
 }
 
-fn add_user(caps: Captures) -> Option<LunchCommand> {
-    let user = caps.get(1)?.as_str();
-    let group = caps.get(2)?.as_str();
-    Some(LunchCommand::AddUser(user, group))
+impl Fail for ParseError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match *self {
+            ParseError::InvalidTime(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
-fn group(caps: Captures) -> Option<LunchCommand> {
-    if let Some(_) = caps.get(1) {
-        let name = caps.get(2)?.as_str();
-        let users = caps.get(3)?.as_str();
-        let users: Vec<&str> = users.split(',').collect();
-        Some(LunchCommand::GroupAdd(name, users))
-    } else if let Some(_) = caps.get(4) {
-        let name = caps.get(5)?.as_str();
-        Some(LunchCommand::GroupRemove(name))
+impl From<TimeError> for ParseError {
+    fn from(e: TimeError) -> ParseError {
+        ParseError::InvalidTime(e)
+    }
+}
+
+/// Levenshtein distance of two strings
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            let value = cmp::min(substitution, cmp::min(previous[j + 1], current[j]) + 1);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Finds the candidate closest to the input, unless they are all too different
+pub fn did_you_mean<'a, I>(input: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = cmp::max(2, input.chars().count() / 3);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(input, c), c))
+        .filter(|&(d, _)| d <= max_distance)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+/// A word of the command line with quotes and escapes already removed
+#[derive(Debug, Eq, PartialEq)]
+struct Token {
+    text: String,
+    /// Byte offset of the token in the line, for commands that need the raw rest of the line
+    start: usize,
+}
+
+/// Splits the line into words. Single or double quotes group words containing spaces together
+/// and a backslash takes the next character literally.
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = vec![];
+    let mut current: Option<Token> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        let token = current.get_or_insert_with(|| Token {
+            text: String::new(),
+            start: i,
+        });
+        match (quote, c) {
+            (_, '\\') => {
+                let (_, escaped) = chars.next().unwrap_or((i, '\\'));
+                token.text.push(escaped);
+            }
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), c) => token.text.push(c),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                // Whitespace only ends a token, it never starts one
+                if token.start == i {
+                    current = None;
+                } else if let Some(token) = current.take() {
+                    tokens.push(token);
+                }
+            }
+            (None, c) => token.text.push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+/// Arguments of a single command, consumed from left to right
+struct Arguments<'a> {
+    command: &'static str,
+    usage: &'static str,
+    line: &'a str,
+    tokens: vec::IntoIter<Token>,
+}
+
+impl<'a> Arguments<'a> {
+    fn invalid(&self) -> ParseError {
+        ParseError::InvalidArguments(self.command, self.usage)
+    }
+
+    fn next(&mut self) -> Result<String, ParseError> {
+        match self.tokens.next() {
+            Some(token) => Ok(token.text),
+            None => Err(self.invalid()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.as_slice().first().map(|t| t.text.as_str())
+    }
+
+    /// Consumes the next argument if it is the given keyword
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.peek() == Some(keyword) {
+            self.tokens.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The rest of the line exactly as it was written
+    fn rest(&mut self) -> Result<String, ParseError> {
+        match self.tokens.next() {
+            Some(token) => Ok(self.line[token.start..].trim_end().to_string()),
+            None => Err(self.invalid()),
+        }
+    }
+
+    /// Comma separated values, spaces after the commas are allowed
+    fn list(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut values = vec![];
+        loop {
+            let value = self.next()?;
+            let more = value.ends_with(',');
+            values.extend(value.split(',').filter(|v| !v.is_empty()).map(String::from));
+            if !more {
+                break;
+            }
+        }
+        if values.is_empty() {
+            return Err(self.invalid());
+        }
+        Ok(values)
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(_) => Err(self.invalid()),
+            None => Ok(()),
+        }
+    }
+
+    fn number(&mut self, argument: &'static str) -> Result<u32, ParseError> {
+        let value = self.next()?;
+        u32::from_str(value.trim_start_matches('#')).map_err(|_| ParseError::InvalidArgument {
+            command: self.command,
+            argument,
+            value,
+        })
+    }
+
+    fn id(&mut self) -> Result<u32, ParseError> {
+        self.number("proposal id")
+    }
+
+    /// Relative times span several words: in 20 min
+    fn time(&mut self) -> Result<LunchTime, ParseError> {
+        let mut time = self.next()?;
+        if time == "in" {
+            let amount = self.next()?;
+            // "in 20 min" has the unit in a word of its own, "in 20min" does not
+            let bare_number = amount.chars().all(|c| c.is_digit(10));
+            time = format!("{} {}", time, amount);
+            if bare_number {
+                time = format!("{} {}", time, self.next()?);
+            }
+        }
+        Ok(parse_time(&time)?)
+    }
+}
+
+fn add(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let first = args.next()?;
+    if args.keyword("to") {
+        let group = args.next()?;
+        args.end()?;
+        Ok(LunchCommand::AddUser(first, group))
     } else {
-        None
+        args.end()?;
+        u32::from_str(&first)
+            .map(LunchCommand::Add)
+            .map_err(|_| args.invalid())
     }
 }
 
-fn propose(caps: Captures) -> Option<LunchCommand> {
-    let place = caps.get(1)?.as_str();
-    let time = caps.get(2)?.as_str();
-    let group = caps.get(3).map(|g| g.as_str());
-    let meeting_point = caps.get(4)
-        .and_then(|place| caps.get(5).map(|time| (place.as_str(), time.as_str())));
-    Some(LunchCommand::Propose(place, time, group, meeting_point))
+fn remove(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let user = args.next()?;
+    if !args.keyword("from") {
+        return Err(args.invalid());
+    }
+    let group = args.next()?;
+    args.end()?;
+    Ok(LunchCommand::RemoveUser(user, group))
 }
 
-fn list(caps: Captures) -> Option<LunchCommand> {
-    if let Some(option) = caps.get(1) {
-        match option.as_str() {
-            "groups" => Some(LunchCommand::List(ListOptions::Groups)),
-            "proposals" => Some(LunchCommand::List(ListOptions::Proposals)),
-            _ => None,
+fn group(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    if args.keyword("add") {
+        let name = args.next()?;
+        let mut users = vec![];
+        while let Ok(list) = args.next() {
+            users.extend(list.split(',').filter(|u| !u.is_empty()).map(String::from));
+        }
+        if users.is_empty() {
+            return Err(args.invalid());
+        }
+        Ok(LunchCommand::GroupAdd(name, users))
+    } else if args.keyword("remove") {
+        let name = args.next()?;
+        args.end()?;
+        Ok(LunchCommand::GroupRemove(name))
+    } else if args.keyword("rename") {
+        let name = args.next()?;
+        let new_name = args.next()?;
+        args.end()?;
+        Ok(LunchCommand::GroupRename(name, new_name))
+    } else if args.keyword("show") {
+        let name = args.next()?;
+        args.end()?;
+        Ok(LunchCommand::GroupShow(name))
+    } else {
+        Err(args.invalid())
+    }
+}
+
+fn propose(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let place = args.next()?;
+    if !args.keyword("at") {
+        args.keyword("@");
+    }
+    let time = args.time()?;
+    let mut group = None;
+    let mut meeting_point = None;
+    while args.peek().is_some() {
+        if group.is_none() && args.keyword("to") {
+            group = Some(args.next()?);
+        } else if meeting_point.is_none() && args.keyword("meet") {
+            let place = args.next()?;
+            meeting_point = Some((place, args.time()?));
+        } else {
+            return Err(args.invalid());
+        }
+    }
+    Ok(LunchCommand::Propose(place, time, group, meeting_point))
+}
+
+fn list(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let option = if args.keyword("groups") {
+        ListOptions::Groups
+    } else if args.keyword("places") {
+        ListOptions::Places
+    } else {
+        args.keyword("proposals");
+        ListOptions::Proposals
+    };
+    args.end()?;
+    Ok(LunchCommand::List(option))
+}
+
+fn place(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let command = if args.keyword("add") {
+        let mut place = Place::new(args.next()?);
+        while args.peek().is_some() {
+            if place.address.is_none() && args.keyword("address") {
+                place.address = Some(args.next()?);
+            } else if place.walking_minutes.is_none() && args.keyword("walk") {
+                place.walking_minutes = Some(args.number("walking minutes")?);
+            } else if place.notes.is_none() && args.keyword("notes") {
+                place.notes = Some(args.next()?);
+            } else if place.tags.is_empty() && args.keyword("tags") {
+                place.tags = args.list()?;
+            } else {
+                return Err(args.invalid());
+            }
+        }
+        PlaceCommand::Add(place)
+    } else if args.keyword("alias") {
+        PlaceCommand::Alias(args.next()?, args.next()?)
+    } else if args.keyword("tag") {
+        PlaceCommand::Tag(args.next()?, args.list()?)
+    } else if args.keyword("remove") {
+        PlaceCommand::Remove(args.next()?)
+    } else if args.keyword("info") {
+        PlaceCommand::Info(args.next()?)
+    } else {
+        return Err(args.invalid());
+    };
+    args.end()?;
+    Ok(LunchCommand::Place(command))
+}
+
+fn suggest(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let mut group = None;
+    let mut time = None;
+    while args.peek().is_some() {
+        if group.is_none() && args.keyword("for") {
+            group = Some(args.next()?);
+        } else if time.is_none() && args.keyword("at") {
+            time = Some(args.time()?);
+        } else {
+            return Err(args.invalid());
+        }
+    }
+    Ok(LunchCommand::Suggest(group, time))
+}
+
+fn poll(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let mut places = vec![];
+    while args.peek().map_or(false, |token| !["by", "at", "to"].contains(&token)) {
+        places.push(args.next()?);
+    }
+    let mut deadline = None;
+    let mut time = None;
+    let mut group = None;
+    while args.peek().is_some() {
+        if deadline.is_none() && args.keyword("by") {
+            deadline = Some(args.time()?);
+        } else if time.is_none() && args.keyword("at") {
+            time = Some(args.time()?);
+        } else if group.is_none() && args.keyword("to") {
+            group = Some(args.next()?);
+        } else {
+            return Err(args.invalid());
         }
+    }
+    // Nothing to choose from, or options without any places
+    let is_shown = deadline.is_none() && time.is_none() && group.is_none();
+    if places.len() == 1 || (places.is_empty() && !is_shown) {
+        return Err(args.invalid());
+    }
+    Ok(LunchCommand::Poll(places, deadline, time, group))
+}
+
+fn veto(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let remove = args.keyword("remove");
+    let place = args.next()?;
+    args.end()?;
+    if remove {
+        Ok(LunchCommand::Unveto(place))
     } else {
-        Some(LunchCommand::List(ListOptions::Proposals))
+        Ok(LunchCommand::Veto(place))
     }
 }
 
-fn dump(_caps: Captures) -> Option<LunchCommand> {
-    Some(LunchCommand::DumpState)
+fn diet(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let tags = if args.keyword("none") {
+        vec![]
+    } else {
+        args.list()?
+    };
+    args.end()?;
+    Ok(LunchCommand::Diet(tags))
 }
 
-fn restore(caps: Captures) -> Option<LunchCommand> {
-    caps.get(1)
-        .map(|c| c.as_str())
-        .map(|s| LunchCommand::RestoreState(s))
+fn stats(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let option = if args.keyword("places") {
+        StatsOptions::Places
+    } else if args.keyword("people") {
+        StatsOptions::People(args.next().ok())
+    } else if args.keyword("time") {
+        StatsOptions::Time
+    } else if args.keyword("streaks") {
+        StatsOptions::Streaks
+    } else {
+        StatsOptions::Overview
+    };
+    args.end()?;
+    Ok(LunchCommand::Stats(option))
 }
 
-pub fn parse_command(line: &str) -> Option<LunchCommand> {
-    if let Some(caps) = ADD_CMD_REGEX.captures(line) {
-        add(caps)
-    } else if let Some(caps) = ADD_USER_CMD_REGEX.captures(line) {
-        add_user(caps)
-    } else if let Some(caps) = GROUP_CMD_REGEX.captures(line) {
-        group(caps)
-    } else if let Some(caps) = PROPOSE_CMD_REGEX.captures(line) {
-        propose(caps)
-    } else if let Some(caps) = LIST_CMD_REGEX.captures(line) {
-        list(caps)
-    } else if let Some(caps) = DUMPSTATE_CMD_REGEX.captures(line) {
-        dump(caps)
-    } else if let Some(caps) = RESTORECONFIG_CMD_REGEX.captures(line) {
-        restore(caps)
+fn edit(mut args: Arguments) -> Result<LunchCommand, ParseError> {
+    let id = args.id()?;
+    let edit = if args.keyword("place") {
+        ProposalEdit::Place(args.next()?)
+    } else if args.keyword("time") {
+        ProposalEdit::Time(args.time()?)
     } else {
-        None
+        return Err(args.invalid());
+    };
+    args.end()?;
+    Ok(LunchCommand::Edit(id, edit))
+}
+
+fn single_id<F>(mut args: Arguments, cmd: F) -> Result<LunchCommand, ParseError>
+where
+    F: Fn(u32) -> LunchCommand,
+{
+    let id = args.id()?;
+    args.end()?;
+    Ok(cmd(id))
+}
+
+/// Strips the way the bot was addressed from the line, one of the `prefixes` or its `nick`
+/// followed by a colon or a comma. Returns `None` when the line is not meant for the bot.
+pub fn command_body<'a>(line: &'a str, prefixes: &[String], nick: &str) -> Option<&'a str> {
+    let line = line.trim_start();
+    // Nicks are case insensitive
+    let addressed = !nick.is_empty()
+        && line
+            .get(..nick.len())
+            .map_or(false, |start| start.eq_ignore_ascii_case(nick))
+        && line[nick.len()..].starts_with(&[':', ','][..]);
+    if addressed {
+        return Some(line[nick.len() + 1..].trim_start());
+    }
+    prefixes
+        .iter()
+        .filter(|prefix| !prefix.is_empty() && line.starts_with(prefix.as_str()))
+        .map(|prefix| &line[prefix.len()..])
+        // `lbx` is not `lb` followed by a command
+        .find(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        .map(str::trim_start)
+}
+
+/// Parses the command without the prefix, the first word names the command
+pub fn parse_command(line: &str) -> Result<LunchCommand, ParseError> {
+    let mut tokens = tokenize(line)?.into_iter();
+    let name = tokens.next().ok_or(ParseError::MissingCommand)?.text;
+    let &(command, usage) = COMMANDS
+        .iter()
+        .find(|&&(command, _)| command == name)
+        .ok_or_else(|| {
+            let suggestion = did_you_mean(&name, COMMANDS.iter().map(|&(command, _)| command));
+            ParseError::UnknownCommand(name.clone(), suggestion)
+        })?;
+    let mut args = Arguments {
+        command,
+        usage,
+        line,
+        tokens,
+    };
+
+    match command {
+        "add" => add(args),
+        "group" => group(args),
+        "remove" => remove(args),
+        "join-group" => {
+            let group = args.next()?;
+            args.end().map(|_| LunchCommand::JoinGroup(group))
+        }
+        "leave-group" => {
+            let group = args.next()?;
+            args.end().map(|_| LunchCommand::LeaveGroup(group))
+        }
+        "propose" => propose(args),
+        "list" => list(args),
+        "dumpstate" => args.end().map(|_| LunchCommand::DumpState),
+        "restore" => args.rest().map(LunchCommand::RestoreState),
+        "join" => single_id(args, LunchCommand::Join),
+        "leave" => single_id(args, LunchCommand::Leave),
+        "cancel" => single_id(args, LunchCommand::Cancel),
+        "edit" => edit(args),
+        "place" => place(args),
+        "menu" => {
+            let place = args.next()?;
+            args.end().map(|_| LunchCommand::Menu(place))
+        }
+        "suggest" => suggest(args),
+        "poll" => poll(args),
+        "vote" => {
+            let option = args.number("option number")?;
+            args.end().map(|_| LunchCommand::Vote(option))
+        }
+        "veto" => veto(args),
+        "diet" => diet(args),
+        "subscribe" => args.end().map(|_| LunchCommand::Subscribe),
+        "unsubscribe" => args.end().map(|_| LunchCommand::Unsubscribe),
+        "stats" => stats(args),
+        "help" => {
+            let topic = args.next().ok();
+            args.end().map(|_| LunchCommand::Help(topic))
+        }
+        _ => Err(args.invalid()),
     }
 }
 
+#[cfg(test)]
+fn at(hour: u32, minute: u32) -> LunchTime {
+    LunchTime::At(::chrono::NaiveTime::from_hms(hour, minute, 0))
+}
+
 #[test]
 fn test_add_cmd() {
-    assert_eq!(Some(LunchCommand::Add(5)), parse_command("lb add 5"))
+    assert_eq!(Ok(LunchCommand::Add(5)), parse_command("add 5"))
 }
 
 #[test]
 fn test_add_user_cmd() {
     assert_eq!(
-        Some(LunchCommand::AddUser("honza", "coreserv1")),
-        parse_command("lb add honza to coreserv1")
+        Ok(LunchCommand::AddUser("honza".into(), "coreserv1".into())),
+        parse_command("add honza to coreserv1")
     )
 }
 
 #[test]
 fn test_group_add_cmd() {
     assert_eq!(
-        Some(LunchCommand::GroupAdd(
-            "coreserv1",
-            vec!["jan", "ondra", "tester"]
+        Ok(LunchCommand::GroupAdd(
+            "coreserv1".into(),
+            vec!["jan".into(), "ondra".into(), "tester".into()]
         )),
-        parse_command("lb group add coreserv1 jan,ondra,tester")
+        parse_command("group add coreserv1 jan,ondra,tester")
     )
 }
 
 #[test]
 fn test_group_remove_cmd() {
     assert_eq!(
-        Some(LunchCommand::GroupRemove("coreserv1")),
-        parse_command("lb group remove coreserv1")
+        Ok(LunchCommand::GroupRemove("coreserv1".into())),
+        parse_command("group remove coreserv1")
     )
 }
 
+#[test]
+fn test_group_membership_cmds() {
+    assert_eq!(
+        Ok(LunchCommand::RemoveUser("jan".into(), "coreserv1".into())),
+        parse_command("remove jan from coreserv1")
+    );
+    assert_eq!(
+        Ok(LunchCommand::GroupRename("coreserv1".into(), "core".into())),
+        parse_command("group rename coreserv1 core")
+    );
+    assert_eq!(
+        Ok(LunchCommand::GroupShow("core".into())),
+        parse_command("group show core")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidArguments("remove", "lb remove <user> from <group>")),
+        parse_command("remove jan coreserv1")
+    );
+    assert_eq!(
+        Ok(LunchCommand::JoinGroup("core".into())),
+        parse_command("join-group core")
+    );
+    assert_eq!(
+        Ok(LunchCommand::LeaveGroup("core".into())),
+        parse_command("leave-group core")
+    );
+}
+
 #[test]
 fn test_list_cmd() {
     assert_eq!(
-        Some(LunchCommand::List(ListOptions::Proposals)),
-        parse_command("lb list")
+        Ok(LunchCommand::List(ListOptions::Proposals)),
+        parse_command("list")
     )
 }
 
 #[test]
 fn test_list_groups_cmd() {
     assert_eq!(
-        Some(LunchCommand::List(ListOptions::Groups)),
-        parse_command("lb list groups")
+        Ok(LunchCommand::List(ListOptions::Groups)),
+        parse_command("list groups")
     )
 }
 
 #[test]
 fn test_list_proposals_cmd() {
     assert_eq!(
-        Some(LunchCommand::List(ListOptions::Proposals)),
-        parse_command("lb list proposals")
+        Ok(LunchCommand::List(ListOptions::Proposals)),
+        parse_command("list proposals")
     )
 }
 
 #[test]
 fn test_propose_cmd() {
     assert_eq!(
-        Some(LunchCommand::Propose("winston", "10:55", None, None)),
-        parse_command("lb propose winston 10:55")
+        Ok(LunchCommand::Propose("winston".into(), at(10, 55), None, None)),
+        parse_command("propose winston 10:55")
     )
 }
 
 #[test]
 fn test_propose_to_group_cmd() {
     assert_eq!(
-        Some(LunchCommand::Propose("winston", "10:55", Some("corserv1"), None)),
-        parse_command("lb propose winston 10:55 to corserv1")
+        Ok(LunchCommand::Propose("winston".into(), at(10, 55), Some("corserv1".into()), None)),
+        parse_command("propose winston 10:55 to corserv1")
     )
 }
 
 #[test]
 fn test_propose_cmd_with_dashes() {
     assert_eq!(
-        Some(LunchCommand::Propose("taste-of-india", "10:55", None, None)),
-        parse_command("lb propose taste-of-india 10:55")
+        Ok(LunchCommand::Propose("taste-of-india".into(), at(10, 55), None, None)),
+        parse_command("propose taste-of-india 10:55")
     )
 }
 
 #[test]
 fn test_propose_cmd_with_quotation_marks() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#""taste of india""#, "10:55", None, None)),
-        parse_command(r#"lb propose "taste of india" 10:55"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(10, 55), None, None)),
+        parse_command(r#"propose "taste of india" 10:55"#)
     )
 }
 
 #[test]
 fn test_propose_cmd_with_quotation_marks2() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#"'taste of india'"#, "10:55", None, None)),
-        parse_command(r#"lb propose 'taste of india' 10:55"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(10, 55), None, None)),
+        parse_command(r#"propose 'taste of india' 10:55"#)
     )
 }
 
 #[test]
 fn test_propose_cmd_with_at() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#"'taste of india'"#, "10:55", None, None)),
-        parse_command(r#"lb propose 'taste of india' at 10:55"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(10, 55), None, None)),
+        parse_command(r#"propose 'taste of india' at 10:55"#)
     )
 }
 
 #[test]
 fn test_propose_cmd_with_at_sign() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#"'taste of india'"#, "10:55", None, None)),
-        parse_command(r#"lb propose 'taste of india' @ 10:55"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(10, 55), None, None)),
+        parse_command(r#"propose 'taste of india' @ 10:55"#)
     )
 }
 
 #[test]
 fn test_propose_cmd_with_meeting_point() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#"'taste of india'"#, "11:00", None,Some((r#""u kulecniku""#, "10:42")))),
-        parse_command(r#"lb propose 'taste of india' @ 11:00 meet "u kulecniku" 10:42"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(11, 0), None,Some(("u kulecniku".into(), at(10, 42))))),
+        parse_command(r#"propose 'taste of india' @ 11:00 meet "u kulecniku" 10:42"#)
     )
 }
 
 #[test]
 fn test_propose_cmd_with_meeting_point_and_group() {
     assert_eq!(
-        Some(LunchCommand::Propose(r#"'taste of india'"#, "11:00", Some("test1"),Some((r#""u kulecniku""#, "10:42")))),
-        parse_command(r#"lb propose 'taste of india' @ 11:00 to test1 meet "u kulecniku" 10:42"#)
+        Ok(LunchCommand::Propose("taste of india".into(), at(11, 0), Some("test1".into()),Some(("u kulecniku".into(), at(10, 42))))),
+        parse_command(r#"propose 'taste of india' @ 11:00 to test1 meet "u kulecniku" 10:42"#)
+    )
+}
+
+#[test]
+fn test_join_cmd() {
+    assert_eq!(Ok(LunchCommand::Join(2)), parse_command("join 2"))
+}
+
+#[test]
+fn test_leave_cmd() {
+    assert_eq!(Ok(LunchCommand::Leave(1)), parse_command("leave 1"))
+}
+
+#[test]
+fn test_join_cmd_with_hash() {
+    assert_eq!(Ok(LunchCommand::Join(12)), parse_command("join #12"))
+}
+
+#[test]
+fn test_cancel_cmd() {
+    assert_eq!(Ok(LunchCommand::Cancel(3)), parse_command("cancel 3"))
+}
+
+#[test]
+fn test_edit_time_cmd() {
+    assert_eq!(
+        Ok(LunchCommand::Edit(3, ProposalEdit::Time(at(12, 15)))),
+        parse_command("edit 3 time 12:15")
     )
 }
+
+#[test]
+fn test_edit_place_cmd() {
+    assert_eq!(
+        Ok(LunchCommand::Edit(3, ProposalEdit::Place("taste of india".into()))),
+        parse_command("edit #3 place 'taste of india'")
+    )
+}
+
+#[test]
+fn test_propose_cmd_with_relative_time() {
+    assert_eq!(
+        Ok(LunchCommand::Propose("winston".into(), LunchTime::InMinutes(20), Some("corserv1".into()), None)),
+        parse_command("propose winston in 20 min to corserv1")
+    )
+}
+
+#[test]
+fn test_propose_cmd_with_invalid_time() {
+    assert_eq!(
+        Err(ParseError::InvalidTime(TimeError::Unrecognized("banana".to_string()))),
+        parse_command("propose winston banana")
+    )
+}
+
+#[test]
+fn test_unknown_command() {
+    assert_eq!(
+        Err(ParseError::UnknownCommand("propse".to_string(), Some("propose"))),
+        parse_command("propse winston 12:00")
+    );
+    assert_eq!(
+        Err(ParseError::UnknownCommand("foo".to_string(), None)),
+        parse_command("foo")
+    );
+}
+
+#[test]
+fn test_invalid_arguments() {
+    assert_eq!(
+        Err(ParseError::InvalidArguments("list", "lb list (groups|proposals|places)")),
+        parse_command("list everything")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidArgument {
+            command: "join",
+            argument: "proposal id",
+            value: "99999999999".to_string(),
+        }),
+        parse_command("join 99999999999")
+    );
+}
+
+#[test]
+fn test_invalid_time_message() {
+    let error = parse_command("propose winston 1260").unwrap_err();
+    assert!(format!("{}", error).starts_with("time `1260` is invalid"));
+}
+
+#[test]
+fn test_did_you_mean() {
+    let groups = vec!["coreserv1", "platform"];
+    assert_eq!(Some("coreserv1"), did_you_mean("corserv", groups.iter().cloned()));
+    assert_eq!(None, did_you_mean("kernel", groups.iter().cloned()));
+}
+
+#[test]
+fn test_tokenize_quotes_and_escapes() {
+    let texts = |line| {
+        tokenize(line)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.text)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(texts(r#"a  "b c" 'd "e"' f\ g"#), vec!["a", "b c", "d \"e\"", "f g"]);
+    assert_eq!(texts(r#"it\'s "" x"#), vec!["it's", "", "x"]);
+    assert_eq!(Err(ParseError::UnterminatedQuote), tokenize("propose 'winston 12:00"));
+}
+
+#[test]
+fn test_propose_cmd_with_diacritics() {
+    assert_eq!(
+        Ok(LunchCommand::Propose("U Kulečníku".into(), at(12, 15), Some("coreserv1".into()), None)),
+        parse_command("propose 'U Kulečníku' 12:15 to coreserv1")
+    )
+}
+
+#[test]
+fn test_propose_cmd_with_clauses_in_any_order() {
+    assert_eq!(
+        Ok(LunchCommand::Propose(
+            "winston".into(),
+            at(12, 0),
+            Some("test1".into()),
+            Some(("lobby".into(), LunchTime::InMinutes(5)))
+        )),
+        parse_command("propose winston noon meet lobby in 5 min to test1")
+    )
+}
+
+#[test]
+fn test_command_must_start_the_line() {
+    let prefixes = vec!["lb".to_string(), "!lunch".to_string()];
+    let body = |line| command_body(line, &prefixes, "lunchbot");
+    assert_eq!(body("did you try lb list"), None);
+    assert_eq!(body("lb add 5 to x"), Some("add 5 to x"));
+    assert_eq!(body("!lunch  list"), Some("list"));
+    assert_eq!(body("lb"), Some(""));
+    assert_eq!(body("lbx list"), None);
+    assert_eq!(body("LunchBot: propose winston"), Some("propose winston"));
+    assert_eq!(body("lunchbot, list"), Some("list"));
+    assert_eq!(body("lunchbots are fun"), None);
+    assert_eq!(
+        Ok(LunchCommand::AddUser("5".into(), "x".into())),
+        parse_command("add 5 to x")
+    );
+    assert_eq!(Err(ParseError::MissingCommand), parse_command(""));
+}
+
+#[test]
+fn test_group_add_cmd_with_spaces() {
+    assert_eq!(
+        Ok(LunchCommand::GroupAdd(
+            "coreserv1".into(),
+            vec!["jan".into(), "ondra".into(), "tester".into()]
+        )),
+        parse_command("group add coreserv1 jan, ondra tester")
+    )
+}
+
+#[test]
+fn test_restore_cmd_keeps_raw_json() {
+    assert_eq!(
+        Ok(LunchCommand::RestoreState(r##"{"groups": [], "channel": "#lunch"}"##.into())),
+        parse_command(r##"restore {"groups": [], "channel": "#lunch"}"##)
+    )
+}
+
+#[test]
+fn test_help_cmd() {
+    assert_eq!(Ok(LunchCommand::Help(None)), parse_command("help"));
+    assert_eq!(
+        Ok(LunchCommand::Help(Some("propose".into()))),
+        parse_command("help propose")
+    );
+}
+
+#[test]
+fn test_stats_cmd() {
+    assert_eq!(
+        Ok(LunchCommand::Stats(StatsOptions::Overview)),
+        parse_command("stats")
+    );
+    assert_eq!(
+        Ok(LunchCommand::Stats(StatsOptions::People(Some("jan".into())))),
+        parse_command("stats people jan")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidArguments(
+            "stats",
+            "lb stats [places|people [<nick>]|time|streaks]"
+        )),
+        parse_command("stats weather")
+    );
+}
+
+#[test]
+fn test_place_cmd() {
+    let mut winston = Place::new("Winston");
+    winston.address = Some("Vinohradská 12".into());
+    winston.walking_minutes = Some(5);
+    assert_eq!(
+        Ok(LunchCommand::Place(PlaceCommand::Add(winston))),
+        parse_command("place add Winston walk 5 address 'Vinohradská 12'")
+    );
+    assert_eq!(
+        Ok(LunchCommand::Place(PlaceCommand::Alias("Winston".into(), "win".into()))),
+        parse_command("place alias Winston win")
+    );
+    assert!(parse_command("place add Winston walk far").is_err());
+}
+
+#[test]
+fn test_menu_cmd() {
+    assert_eq!(
+        Ok(LunchCommand::Menu("U Kulečníku".into())),
+        parse_command("menu \"U Kulečníku\"")
+    );
+    assert_eq!(
+        Err(ParseError::InvalidArguments("menu", "lb menu <place>")),
+        parse_command("menu U Kulečníku")
+    );
+}
+
+#[test]
+fn test_suggest_cmd() {
+    assert_eq!(Ok(LunchCommand::Suggest(None, None)), parse_command("suggest"));
+    assert_eq!(
+        Ok(LunchCommand::Suggest(Some("team".into()), Some(at(12, 0)))),
+        parse_command("suggest for team at 12:00")
+    );
+    assert_eq!(
+        Ok(LunchCommand::Place(PlaceCommand::Tag(
+            "Winston".into(),
+            vec!["vegan".into(), "gluten free".into()]
+        ))),
+        parse_command("place tag Winston vegan, 'gluten free'")
+    );
+    assert_eq!(Ok(LunchCommand::Unveto("pho".into())), parse_command("veto remove pho"));
+    assert_eq!(Ok(LunchCommand::Diet(vec![])), parse_command("diet none"));
+    assert!(parse_command("diet").is_err());
+}
+
+#[test]
+fn test_poll_cmd() {
+    assert_eq!(
+        Ok(LunchCommand::Poll(
+            vec!["winston".into(), "U Kulečníku".into(), "pho".into()],
+            Some(at(11, 30)),
+            None,
+            Some("team".into())
+        )),
+        parse_command("poll winston 'U Kulečníku' pho by 11:30 to team")
+    );
+    assert_eq!(Ok(LunchCommand::Poll(vec![], None, None, None)), parse_command("poll"));
+    assert!(parse_command("poll winston by 11:30").is_err());
+    assert!(parse_command("poll by 11:30").is_err());
+    assert_eq!(Ok(LunchCommand::Vote(2)), parse_command("vote 2"));
+}
@@ -0,0 +1,105 @@
+use chrono::{Duration, NaiveTime};
+use regex::Regex;
+use std::str::FromStr;
+
+lazy_static! {
+    static ref CLOCK_TIME_REGEX: Regex = Regex::new(r"^(\d{1,2})(?:[:.](\d{2}))?$").unwrap();
+    static ref RELATIVE_TIME_REGEX: Regex =
+        Regex::new(r"^in (\d+) ?(m|min|mins|minutes?|h|hours?)$").unwrap();
+}
+
+/// Time of day as written by the user. Relative times ("in 20 min") can only be turned into
+/// a time of day once we know the current time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LunchTime {
+    At(NaiveTime),
+    InMinutes(u32),
+}
+
+impl LunchTime {
+    pub fn resolve(&self, now: NaiveTime) -> NaiveTime {
+        match *self {
+            LunchTime::At(time) => time,
+            LunchTime::InMinutes(minutes) => now + Duration::minutes(i64::from(minutes)),
+        }
+    }
+}
+
+#[derive(Debug, Fail, Eq, PartialEq)]
+pub enum TimeError {
+    #[fail(display = "time `{}` is invalid, try something like 12:00, 12.30, noon or in 20 min", _0)]
+    Unrecognized(String),
+    #[fail(display = "time `{}` is invalid, there is no such hour or minute", _0)]
+    OutOfRange(String),
+}
+
+/// Accepts 12:00, 12.30, 12, noon and relative times like in 20 min or in 1 hour
+pub fn parse_time(input: &str) -> Result<LunchTime, TimeError> {
+    let input = input.trim();
+    if input == "noon" {
+        return Ok(LunchTime::At(NaiveTime::from_hms(12, 0, 0)));
+    }
+
+    if let Some(caps) = CLOCK_TIME_REGEX.captures(input) {
+        let hour = u32::from_str(&caps[1]).unwrap_or(99);
+        let minute = caps
+            .get(2)
+            .map(|m| u32::from_str(m.as_str()).unwrap_or(99))
+            .unwrap_or(0);
+        NaiveTime::from_hms_opt(hour, minute, 0)
+            .map(LunchTime::At)
+            .ok_or_else(|| TimeError::OutOfRange(input.to_string()))
+    } else if let Some(caps) = RELATIVE_TIME_REGEX.captures(input) {
+        let amount = u32::from_str(&caps[1])
+            .ok()
+            .filter(|a| *a <= 24 * 60)
+            .ok_or_else(|| TimeError::OutOfRange(input.to_string()))?;
+        if caps[2].starts_with('h') {
+            Ok(LunchTime::InMinutes(amount * 60))
+        } else {
+            Ok(LunchTime::InMinutes(amount))
+        }
+    } else {
+        Err(TimeError::Unrecognized(input.to_string()))
+    }
+}
+
+#[test]
+fn test_parse_clock_times() {
+    let expected = Ok(LunchTime::At(NaiveTime::from_hms(12, 30, 0)));
+    assert_eq!(expected, parse_time("12:30"));
+    assert_eq!(expected, parse_time("12.30"));
+    assert_eq!(Ok(LunchTime::At(NaiveTime::from_hms(11, 0, 0))), parse_time("11"));
+    assert_eq!(Ok(LunchTime::At(NaiveTime::from_hms(12, 0, 0))), parse_time("noon"));
+}
+
+#[test]
+fn test_parse_relative_times() {
+    assert_eq!(Ok(LunchTime::InMinutes(20)), parse_time("in 20 min"));
+    assert_eq!(Ok(LunchTime::InMinutes(5)), parse_time("in 5 minutes"));
+    assert_eq!(Ok(LunchTime::InMinutes(60)), parse_time("in 1 hour"));
+    assert_eq!(
+        NaiveTime::from_hms(11, 50, 0),
+        LunchTime::InMinutes(20).resolve(NaiveTime::from_hms(11, 30, 0))
+    );
+}
+
+#[test]
+fn test_parse_invalid_times() {
+    assert_eq!(
+        Err(TimeError::Unrecognized("banana".to_string())),
+        parse_time("banana")
+    );
+    assert_eq!(
+        Err(TimeError::Unrecognized("1260".to_string())),
+        parse_time("1260")
+    );
+    assert_eq!(
+        Err(TimeError::OutOfRange("12:60".to_string())),
+        parse_time("12:60")
+    );
+    assert_eq!(
+        Err(TimeError::OutOfRange("25".to_string())),
+        parse_time("25")
+    );
+}
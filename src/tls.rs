@@ -0,0 +1,97 @@
+use failure::Error;
+use futures::{Future, Stream};
+use native_tls::backend::openssl::TlsConnectorBuilderExt;
+use native_tls::{Certificate, TlsConnector};
+use openssl::x509::X509_FILETYPE_PEM;
+use std::fs::File;
+use std::io::prelude::*;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_io::io::{copy, shutdown};
+use tokio_io::AsyncRead;
+use tokio_tls::TlsConnectorExt;
+
+/// PEM encoded certificate and private key the bot presents to the server
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClientCert {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Opens the TLS connection with a client certificate on behalf of the irc crate, which can only
+/// add a CA. The irc crate connects to a local port in plain text instead of the server.
+pub struct Relay {
+    server: String,
+    port: u16,
+    connector: TlsConnector,
+}
+
+impl Relay {
+    pub fn new(
+        server: &str,
+        port: u16,
+        ca_cert: Option<&Path>,
+        client_cert: &ClientCert,
+    ) -> Result<Relay, Error> {
+        let mut builder = TlsConnector::builder()?;
+        // DER encoded like the irc crate expects it
+        if let Some(path) = ca_cert {
+            let mut der = vec![];
+            File::open(path)?.read_to_end(&mut der)?;
+            builder.add_root_certificate(Certificate::from_der(&der)?)?;
+        }
+        {
+            let ssl = builder.builder_mut();
+            ssl.set_certificate_chain_file(&client_cert.cert)?;
+            ssl.set_private_key_file(&client_cert.key, X509_FILETYPE_PEM)?;
+            ssl.check_private_key()?;
+        }
+
+        Ok(Relay {
+            server: server.to_string(),
+            port,
+            connector: builder.build()?,
+        })
+    }
+
+    /// Listens on a loopback port and relays the first connection to it to the server, the irc
+    /// crate has to connect to the returned address right away
+    pub fn listen(&self, handle: &Handle) -> Result<SocketAddr, Error> {
+        let server = (self.server.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format_err!("{} has no address", self.server))?;
+        let listener = TcpListener::bind(&SocketAddr::from(([127, 0, 0, 1], 0)), handle)?;
+        let local = listener.local_addr()?;
+
+        let connector = self.connector.clone();
+        let domain = self.server.clone();
+        let connect_handle = handle.clone();
+        let relay = listener
+            .incoming()
+            .take(1)
+            .map_err(Error::from)
+            .for_each(move |(plain, _)| {
+                let connector = connector.clone();
+                let domain = domain.clone();
+                TcpStream::connect(&server, &connect_handle)
+                    .map_err(Error::from)
+                    .and_then(move |tcp| connector.connect_async(&domain, tcp).map_err(Error::from))
+                    .and_then(move |secured| {
+                        let (from_server, to_server) = secured.split();
+                        let (from_bot, to_bot) = plain.split();
+                        let upstream =
+                            copy(from_bot, to_server).and_then(|(_, _, to)| shutdown(to));
+                        let downstream =
+                            copy(from_server, to_bot).and_then(|(_, _, to)| shutdown(to));
+                        upstream.join(downstream).map(|_| ()).map_err(Error::from)
+                    })
+            })
+            .map_err(|e| error!("TLS relay failed: {}", e));
+        handle.spawn(relay);
+
+        Ok(local)
+    }
+}